@@ -0,0 +1,260 @@
+use glfw::Key;
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Keys used by the viewer. Escape always quits as well, and the arrow keys always
+/// move the crop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyBindings {
+    pub up: Key,
+    pub down: Key,
+    pub left: Key,
+    pub right: Key,
+    pub quit: Key,
+    pub reset: Key,
+    pub save: Key,
+    /// toggles the magic wand, where a click crops to the similar colored region under it
+    pub wand: Key,
+    /// toggles showing the pixel under the cursor in the title
+    pub cursor: Key,
+}
+
+/// User settings, read from `$XDG_CONFIG_HOME/motsu/config.toml`.
+///
+/// Only a small subset of TOML is understood:
+///
+/// ```toml
+/// nudge = 1
+/// fast_nudge = 10
+///
+/// [keys]
+/// up = "k"
+/// down = "j"
+/// left = "h"
+/// right = "l"
+/// quit = "q"
+/// reset = "r"
+/// save = "w"
+/// wand = "m"
+/// cursor = "p"
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// pixels cropped per key press
+    pub nudge: u32,
+    /// pixels cropped per key press while holding ctrl
+    pub fast_nudge: u32,
+    pub keys: KeyBindings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            nudge: 1,
+            fast_nudge: 10,
+            keys: KeyBindings {
+                up: Key::K,
+                down: Key::J,
+                left: Key::H,
+                right: Key::L,
+                quit: Key::Q,
+                reset: Key::R,
+                save: Key::W,
+                wand: Key::M,
+                cursor: Key::P,
+            },
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file. A missing file gives the defaults, and a malformed one
+    /// gives the defaults with a warning.
+    pub fn load() -> Config {
+        match config_path() {
+            Some(path) => Config::load_from(&path),
+            None => Default::default(),
+        }
+    }
+
+    fn load_from(path: &Path) -> Config {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Default::default(),
+            Err(e) => {
+                eprintln!("cannot read {}: {}", path.display(), e);
+                return Default::default();
+            }
+        };
+
+        match Config::parse(&contents) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("ignoring {}: {}", path.display(), e);
+                Default::default()
+            }
+        }
+    }
+
+    /// Parse the contents of a config file. Settings that are not given keep their
+    /// default values. Binding one key to two actions is an error.
+    pub fn parse(contents: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut section = "";
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim();
+                continue;
+            }
+
+            let error = |msg: String| format!("line {}: {}", number + 1, msg);
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `name = value`".to_string()))?;
+            let (name, value) = (name.trim(), value.trim());
+
+            match (section, name) {
+                ("", "nudge") => config.nudge = parse_amount(value).map_err(error)?,
+                ("", "fast_nudge") => config.fast_nudge = parse_amount(value).map_err(error)?,
+                ("keys", "up") => config.keys.up = parse_key(value).map_err(error)?,
+                ("keys", "down") => config.keys.down = parse_key(value).map_err(error)?,
+                ("keys", "left") => config.keys.left = parse_key(value).map_err(error)?,
+                ("keys", "right") => config.keys.right = parse_key(value).map_err(error)?,
+                ("keys", "quit") => config.keys.quit = parse_key(value).map_err(error)?,
+                ("keys", "reset") => config.keys.reset = parse_key(value).map_err(error)?,
+                ("keys", "save") => config.keys.save = parse_key(value).map_err(error)?,
+                ("keys", "wand") => config.keys.wand = parse_key(value).map_err(error)?,
+                ("keys", "cursor") => config.keys.cursor = parse_key(value).map_err(error)?,
+                _ => return Err(error(format!("unknown setting `{}`", name))),
+            }
+        }
+
+        config.keys.check_duplicates()?;
+        Ok(config)
+    }
+}
+
+impl KeyBindings {
+    fn check_duplicates(&self) -> Result<(), String> {
+        let bindings = [
+            ("up", self.up),
+            ("down", self.down),
+            ("left", self.left),
+            ("right", self.right),
+            ("quit", self.quit),
+            ("reset", self.reset),
+            ("save", self.save),
+            ("wand", self.wand),
+            ("cursor", self.cursor),
+        ];
+        for (i, (name, key)) in bindings.iter().enumerate() {
+            if let Some((other, _)) = bindings[i + 1..].iter().find(|(_, k)| k == key) {
+                return Err(format!(
+                    "`{}` and `{}` are bound to the same key",
+                    name, other
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("motsu").join("config.toml"))
+}
+
+fn parse_amount(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(amount) if amount > 0 => Ok(amount),
+        _ => Err(format!("expected a positive integer, found `{}`", value)),
+    }
+}
+
+fn parse_key(value: &str) -> Result<Key, String> {
+    let name = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a quoted key name, found `{}`", value))?;
+
+    let key = match name.to_ascii_lowercase().as_str() {
+        "a" => Key::A,
+        "b" => Key::B,
+        "c" => Key::C,
+        "d" => Key::D,
+        "e" => Key::E,
+        "f" => Key::F,
+        "g" => Key::G,
+        "h" => Key::H,
+        "i" => Key::I,
+        "j" => Key::J,
+        "k" => Key::K,
+        "l" => Key::L,
+        "m" => Key::M,
+        "n" => Key::N,
+        "o" => Key::O,
+        "p" => Key::P,
+        "q" => Key::Q,
+        "r" => Key::R,
+        "s" => Key::S,
+        "t" => Key::T,
+        "u" => Key::U,
+        "v" => Key::V,
+        "w" => Key::W,
+        "x" => Key::X,
+        "y" => Key::Y,
+        "z" => Key::Z,
+        _ => return Err(format!("unsupported key `{}`", name)),
+    };
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_file_gives_defaults() {
+        let path = Path::new("/nonexistent/motsu/config.toml");
+        assert_eq!(Config::load_from(path), Config::default());
+    }
+
+    #[test]
+    fn partial_config_keeps_other_defaults() {
+        let config = Config::parse("fast_nudge = 25\n\n[keys]\nsave = \"s\" # comment\n").unwrap();
+        let defaults = Config::default();
+        assert_eq!(config.nudge, defaults.nudge);
+        assert_eq!(config.fast_nudge, 25);
+        assert_eq!(config.keys.save, Key::S);
+        assert_eq!(config.keys.up, defaults.keys.up);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = Config::parse("nudge = 1\nnudge = 0\n").unwrap_err();
+        assert!(error.starts_with("line 2:"), "{}", error);
+        assert!(Config::parse("[keys]\njump = \"x\"\n").is_err());
+        assert!(Config::parse("[keys]\nup = k\n").is_err());
+    }
+
+    #[test]
+    fn duplicate_bindings_are_rejected() {
+        assert!(Config::parse("[keys]\nsave = \"h\"\n").is_err());
+        assert!(Config::parse("[keys]\nwand = \"k\"\n").is_err());
+        assert!(Config::parse("[keys]\ncursor = \"m\"\n").is_err());
+        // moving a binding out of the way first is fine
+        assert!(Config::parse("[keys]\nleft = \"a\"\nsave = \"h\"\n").is_ok());
+    }
+}
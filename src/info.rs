@@ -0,0 +1,297 @@
+use image::io::Reader;
+use image::ImageFormat;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+
+const INCHES_PER_METER: f64 = 39.3701;
+
+/// Metadata about an image file, as printed by `--list-info` and `--info-json`.
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: &'static str,
+    pub channels: u8,
+    pub bit_depth: u8,
+    /// horizontal and vertical resolution, if the file records one
+    pub dpi: Option<(f64, f64)>,
+    /// encoding gamma of the samples, from the gAMA or sRGB chunk
+    pub gamma: Option<f64>,
+    /// keyword and text of each text chunk
+    pub text: Vec<(String, String)>,
+}
+
+impl ImageInfo {
+    /// Read the metadata of an image. PNGs are inspected without decoding the pixel
+    /// data, so the color type and bit depth are those stored in the file.
+    pub fn read(path: &str) -> Result<ImageInfo, Box<dyn Error>> {
+        let reader = Reader::open(path)?.with_guessed_format()?;
+        if reader.format() == Some(ImageFormat::Png) {
+            return ImageInfo::read_png(path);
+        }
+
+        let image = reader.decode()?;
+        let color = image.color();
+        let channels = color.channel_count();
+        Ok(ImageInfo {
+            width: image.width(),
+            height: image.height(),
+            color_type: match channels {
+                1 => "gray",
+                2 => "gray-alpha",
+                3 => "rgb",
+                _ => "rgba",
+            },
+            channels,
+            bit_depth: (color.bits_per_pixel() / channels as u16) as u8,
+            dpi: None,
+            gamma: None,
+            text: Vec::new(),
+        })
+    }
+
+    fn read_png(path: &str) -> Result<ImageInfo, Box<dyn Error>> {
+        let decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        let reader = decoder.read_info()?;
+        let info = reader.info();
+
+        let dpi = info.pixel_dims.and_then(|dims| match dims.unit {
+            png::Unit::Meter => Some((
+                dims.xppu as f64 / INCHES_PER_METER,
+                dims.yppu as f64 / INCHES_PER_METER,
+            )),
+            png::Unit::Unspecified => None,
+        });
+
+        let mut text = Vec::new();
+        for chunk in &info.uncompressed_latin1_text {
+            text.push((chunk.keyword.clone(), chunk.text.clone()));
+        }
+        for chunk in &info.compressed_latin1_text {
+            text.push((chunk.keyword.clone(), chunk.get_text()?));
+        }
+        for chunk in &info.utf8_text {
+            text.push((chunk.keyword.clone(), chunk.get_text()?));
+        }
+
+        Ok(ImageInfo {
+            width: info.width,
+            height: info.height,
+            color_type: match info.color_type {
+                png::ColorType::Grayscale => "gray",
+                png::ColorType::GrayscaleAlpha => "gray-alpha",
+                png::ColorType::Rgb => "rgb",
+                png::ColorType::Rgba => "rgba",
+                png::ColorType::Indexed => "indexed",
+            },
+            channels: info.color_type.samples() as u8,
+            bit_depth: info.bit_depth as u8,
+            dpi,
+            gamma: info.source_gamma.map(|gamma| gamma.into_value() as f64),
+            text,
+        })
+    }
+
+    /// Print the metadata as `key: value` lines.
+    pub fn print(&self) {
+        println!("width: {}", self.width);
+        println!("height: {}", self.height);
+        println!("color type: {}", self.color_type);
+        println!("channels: {}", self.channels);
+        println!("bit depth: {}", self.bit_depth);
+        if let Some((x, y)) = self.dpi {
+            println!("dpi: {:.0}x{:.0}", x, y);
+        }
+        if let Some(gamma) = self.gamma {
+            println!("gamma: {:.5}", gamma);
+        }
+        for (keyword, text) in &self.text {
+            println!("{}: {}", keyword, text);
+        }
+    }
+
+    /// Print the metadata as a single JSON object.
+    pub fn print_json(&self) {
+        println!("{}", self.to_json());
+    }
+
+    /// The metadata as a single JSON object. Text chunks are listed in file order, as a
+    /// keyword may be used more than once.
+    fn to_json(&self) -> String {
+        let dpi = match self.dpi {
+            Some((x, y)) => format!("[{:.0},{:.0}]", x, y),
+            None => "null".to_string(),
+        };
+        let gamma = match self.gamma {
+            Some(gamma) => format!("{:.5}", gamma),
+            None => "null".to_string(),
+        };
+        let text: Vec<String> = self
+            .text
+            .iter()
+            .map(|(keyword, text)| {
+                format!(
+                    "{{\"keyword\":{},\"text\":{}}}",
+                    json_string(keyword),
+                    json_string(text)
+                )
+            })
+            .collect();
+        let fields = [
+            format!("\"width\":{}", self.width),
+            format!("\"height\":{}", self.height),
+            format!("\"color_type\":{}", json_string(self.color_type)),
+            format!("\"channels\":{}", self.channels),
+            format!("\"bit_depth\":{}", self.bit_depth),
+            format!("\"dpi\":{}", dpi),
+            format!("\"gamma\":{}", gamma),
+            format!("\"text\":[{}]", text.join(",")),
+        ];
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// Whether a file is an animated PNG. Only its default image is decoded, so the
+/// animation is lost when cropping it.
+pub fn is_apng(path: &str) -> bool {
+    matches!(read_png_header(path), Some(reader) if reader.info().animation_control.is_some())
+}
+
+/// The encoding gamma recorded in a PNG file, if any.
+pub fn file_gamma(path: &str) -> Option<f64> {
+    let reader = read_png_header(path)?;
+    let gamma = reader.info().source_gamma?.into_value() as f64;
+    // a gamma of 0 can't be corrected for
+    if gamma > 0.0 {
+        Some(gamma)
+    } else {
+        None
+    }
+}
+
+/// Read the chunks before the image data of a PNG file, or `None` if it can't be read
+/// as a PNG.
+fn read_png_header(path: &str) -> Option<png::Reader<BufReader<File>>> {
+    let file = File::open(path).ok()?;
+    png::Decoder::new(BufReader::new(file)).read_info().ok()
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::RgbImage;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn json_lists_repeated_keywords() {
+        let info = ImageInfo {
+            width: 40,
+            height: 30,
+            color_type: "rgb",
+            channels: 3,
+            bit_depth: 8,
+            dpi: None,
+            gamma: Some(0.45455),
+            text: vec![
+                ("Comment".to_string(), "first".to_string()),
+                ("Comment".to_string(), "say \"hi\"".to_string()),
+            ],
+        };
+        assert_eq!(
+            info.to_json(),
+            concat!(
+                r#"{"width":40,"height":30,"color_type":"rgb","channels":3,"bit_depth":8,"#,
+                r#""dpi":null,"gamma":0.45455,"text":[{"keyword":"Comment","text":"first"},"#,
+                r#"{"keyword":"Comment","text":"say \"hi\""}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn read_png_dimensions() {
+        let path = env::temp_dir().join(format!("motsu-test-{}-info.png", process::id()));
+        RgbImage::new(40, 30).save(&path).unwrap();
+        let info = ImageInfo::read(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let info = info.unwrap();
+        assert_eq!((info.width, info.height), (40, 30));
+        assert_eq!(info.color_type, "rgb");
+        assert!(info.to_json().starts_with(r#"{"width":40,"height":30,"#));
+        assert_eq!(info.gamma, None);
+    }
+
+    #[test]
+    fn read_png_gamma() {
+        let path = env::temp_dir().join(format!("motsu-test-{}-gamma.png", process::id()));
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 2, 2);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_source_gamma(png::ScaledFloat::new(0.5));
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 64, 128, 255]).unwrap();
+        writer.finish().unwrap();
+
+        let info = ImageInfo::read(path.to_str().unwrap());
+        let gamma = file_gamma(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(info.unwrap().gamma, Some(0.5));
+        assert_eq!(gamma, Some(0.5));
+    }
+
+    #[test]
+    fn apng_loads_its_default_image() {
+        let path = env::temp_dir().join(format!("motsu-test-{}-anim.png", process::id()));
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 3, 2);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_animated(2, 0).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0; 6]).unwrap();
+        writer.write_image_data(&[255; 6]).unwrap();
+        writer.finish().unwrap();
+
+        let animated = is_apng(path.to_str().unwrap());
+        let image = image::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(animated);
+        let image = image.unwrap();
+        assert_eq!((image.width(), image.height()), (3, 2));
+        assert!(image.as_bytes().iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn still_images_are_not_apng() {
+        let png = env::temp_dir().join(format!("motsu-test-{}-still.png", process::id()));
+        let jpeg = env::temp_dir().join(format!("motsu-test-{}-still.jpg", process::id()));
+        RgbImage::new(4, 4).save(&png).unwrap();
+        RgbImage::new(4, 4).save(&jpeg).unwrap();
+        let animated = [
+            is_apng(png.to_str().unwrap()),
+            is_apng(jpeg.to_str().unwrap()),
+        ];
+        fs::remove_file(&png).unwrap();
+        fs::remove_file(&jpeg).unwrap();
+        assert_eq!(animated, [false, false]);
+    }
+}
@@ -1,8 +1,15 @@
+mod config;
+mod info;
+
 use argh::FromArgs;
 use glfw::{
     Action, Context as _, Key, Modifiers, MouseButton, SwapInterval, WindowEvent, WindowMode,
 };
-use image::RgbaImage;
+use image::imageops::FilterType;
+use image::{
+    ColorType, DynamicImage, GenericImageView, ImageBuffer, ImageFormat, ImageOutputFormat,
+    ImageResult, Rgb, RgbImage, Rgba, Rgba32FImage, RgbaImage,
+};
 use luminance::blending::{Blending, Equation, Factor};
 use luminance::context::GraphicsContext;
 use luminance::pipeline::{PipelineState, TextureBinding};
@@ -14,10 +21,19 @@ use luminance::texture::{Dim2, Sampler, TexelUpload, Texture};
 use luminance_derive::{Semantics, UniformInterface, Vertex};
 use luminance_glfw::{GL33Context, GlfwSurface, GlfwSurfaceError};
 
+use config::Config;
+use info::{file_gamma, is_apng, ImageInfo};
+
 use std::cmp::{max, min};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
 use std::process::exit;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 struct Crop {
     left: u32,
     right: u32,
@@ -25,6 +41,65 @@ struct Crop {
     bottom: u32,
 }
 
+/// A crop with fractional sides, as selected with the mouse on a downscaled image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SubpixelCrop {
+    left: f64,
+    right: f64,
+    top: f64,
+    bottom: f64,
+}
+
+impl Crop {
+    /// The crop equivalent to applying `self` and then `other` to the result. Sides that
+    /// overflow saturate, which no image is large enough to keep anything of.
+    fn compose(self, other: Crop) -> Crop {
+        Crop {
+            left: self.left.saturating_add(other.left),
+            right: self.right.saturating_add(other.right),
+            top: self.top.saturating_add(other.top),
+            bottom: self.bottom.saturating_add(other.bottom),
+        }
+    }
+}
+
+/// Formats as `l:10,r:5,t:0,b:0`, the same format accepted by `--crop-spec`.
+impl fmt::Display for Crop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "l:{},r:{},t:{},b:{}",
+            self.left, self.right, self.top, self.bottom
+        )
+    }
+}
+
+/// Parses `side:amount` pairs separated by commas. Sides may be given in any
+/// order and missing sides are not cropped.
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Crop, String> {
+        let error = || format!("invalid crop spec: {}", spec);
+        let mut crop = Crop::default();
+        for part in spec.split(',') {
+            let (side, amount) = part.split_once(':').ok_or_else(error)?;
+            let amount = amount.trim().parse().map_err(|_| error())?;
+            match side.trim() {
+                "l" => crop.left = amount,
+                "r" => crop.right = amount,
+                "t" => crop.top = amount,
+                "b" => crop.bottom = amount,
+                _ => return Err(error()),
+            }
+        }
+        Ok(crop)
+    }
+}
+
+/// How often `--watch` checks the input for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
 const VS: &str = include_str!("texture-vs.glsl");
 const FS: &str = include_str!("texture-fs.glsl");
 type GlfwBackend = <GL33Context as GraphicsContext>::Backend;
@@ -56,12 +131,27 @@ struct ShaderInterface {
     tex: Uniform<TextureBinding<Dim2, NormUnsigned>>,
 }
 
+/// Direction of `--flip`.
+#[derive(Clone, Copy, Debug)]
+enum Flip {
+    Horizontal,
+    Vertical,
+}
+
+/// Output size requested with `--scale`.
+#[derive(Clone, Copy, Debug)]
+enum Scale {
+    Factor(f64),
+    Size(u32, u32),
+}
+
 #[derive(FromArgs, Debug)]
 /// Image viewer and cropper. Use hjkl keys to crop image.
 ///
 /// Hold CTRL to increase cropping speed, hold shift to uncrop a side.
 ///
-/// Press q or escape to quit, and r to undo all cropping.
+/// Press q or escape to quit, r to undo all cropping, and w to save the
+/// current crop without quitting.
 ///
 /// You may also click twice on the image to crop with the bounding rectangle
 /// of the two mouse clicks.
@@ -70,10 +160,46 @@ struct PNGArgs {
     #[argh(switch, short = 'q')]
     quiet: bool,
 
+    /// print the output size and path without writing anything or opening the viewer
+    #[argh(switch)]
+    dry_run: bool,
+
     /// save to input file
     #[argh(switch, short = 'i')]
     in_place: bool,
 
+    /// don't enlarge images smaller than the window
+    #[argh(switch)]
+    no_resize_small: bool,
+
+    /// resample mouse crops so that selections between pixels are kept exactly
+    #[argh(switch)]
+    antialias_crop: bool,
+
+    /// save each crop to a numbered file when pressing w, then reset the crop
+    #[argh(switch)]
+    multi: bool,
+
+    /// reload the input in the viewer whenever it changes on disk
+    #[argh(switch)]
+    watch: bool,
+
+    /// open the viewer on this monitor, counting from 0 (default: primary monitor)
+    #[argh(option)]
+    monitor: Option<usize>,
+
+    /// print timings and image sizes to stderr
+    #[argh(switch, short = 'v')]
+    verbose: bool,
+
+    /// print image metadata and exit
+    #[argh(switch)]
+    list_info: bool,
+
+    /// print image metadata as json and exit
+    #[argh(switch)]
+    info_json: bool,
+
     /// output file
     #[argh(option, short = 'o')]
     output: Option<String>,
@@ -94,18 +220,615 @@ struct PNGArgs {
     #[argh(option, short = 'b')]
     crop_bottom: Option<u32>,
 
-    /// scale
-    #[argh(option, short = 's')]
-    scale: Option<f64>,
+    /// crop to a centered region, given as WxH
+    #[argh(option, from_str_fn(parse_size))]
+    crop_center: Option<(u32, u32)>,
+
+    /// keep the crop centered while resizing it in the viewer
+    #[argh(switch)]
+    center: bool,
+
+    /// crop given as l:N,r:N,t:N,b:N, applied before the edge crops
+    #[argh(option)]
+    crop_spec: Option<Crop>,
+
+    /// rotate clockwise by 90, 180, or 270 degrees
+    #[argh(option, from_str_fn(parse_rotation))]
+    rotate: Option<u32>,
+
+    /// flip horizontally (h) or vertically (v), after rotating
+    #[argh(option, from_str_fn(parse_flip))]
+    flip: Option<Flip>,
+
+    /// scale factor, or output size as WxH
+    #[argh(option, short = 's', from_str_fn(parse_scale))]
+    scale: Option<Scale>,
+
+    /// horizontal scale factor, on top of a --scale factor
+    #[argh(option)]
+    scale_x: Option<f64>,
+
+    /// vertical scale factor, on top of a --scale factor
+    #[argh(option)]
+    scale_y: Option<f64>,
+
+    /// scaling filter: nearest, bilinear, or lanczos (default)
+    #[argh(option, from_str_fn(parse_filter))]
+    filter: Option<FilterType>,
+
+    /// flatten transparency onto a background color, given as RRGGBB
+    #[argh(option, from_str_fn(parse_color))]
+    background: Option<Rgb<u8>>,
+
+    /// save as single-channel grayscale
+    #[argh(switch)]
+    grayscale: bool,
+
+    /// bits per sample of the output, 8 or 16 (default: same as the input)
+    #[argh(option, from_str_fn(parse_bit_depth))]
+    output_bitdepth: Option<u8>,
+
+    /// correct the samples of a png with a gAMA chunk for this display gamma, e.g. 2.2
+    #[argh(option, from_str_fn(parse_gamma))]
+    apply_gamma: Option<f64>,
+
+    /// jpeg quality, from 0 to 100 (default 75)
+    #[argh(option)]
+    quality: Option<u8>,
 
     #[argh(positional)]
     input: String,
 }
 
-fn crop_image(image: &mut RgbaImage, crop: Crop) -> RgbaImage {
+fn parse_rotation(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(degrees @ (90 | 180 | 270)) => Ok(degrees),
+        _ => Err(format!("rotation must be 90, 180, or 270: {}", value)),
+    }
+}
+
+fn parse_bit_depth(value: &str) -> Result<u8, String> {
+    match value.parse() {
+        Ok(depth @ (8 | 16)) => Ok(depth),
+        _ => Err(format!("bit depth must be 8 or 16: {}", value)),
+    }
+}
+
+fn parse_gamma(value: &str) -> Result<f64, String> {
+    match value.parse() {
+        Ok(gamma) if gamma > 0.0 && f64::is_finite(gamma) => Ok(gamma),
+        _ => Err(format!("gamma must be a positive number: {}", value)),
+    }
+}
+
+fn parse_flip(value: &str) -> Result<Flip, String> {
+    match value {
+        "h" => Ok(Flip::Horizontal),
+        "v" => Ok(Flip::Vertical),
+        _ => Err(format!("flip must be h or v: {}", value)),
+    }
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let error = || format!("invalid size: {}", value);
+    let (width, height) = value.split_once('x').ok_or_else(error)?;
+    Ok((
+        width.parse().map_err(|_| error())?,
+        height.parse().map_err(|_| error())?,
+    ))
+}
+
+fn parse_scale(value: &str) -> Result<Scale, String> {
+    if value.contains('x') {
+        parse_size(value).map(|(width, height)| Scale::Size(width, height))
+    } else {
+        value
+            .parse()
+            .map(Scale::Factor)
+            .map_err(|_| format!("invalid scale: {}", value))
+    }
+}
+
+fn parse_filter(value: &str) -> Result<FilterType, String> {
+    match value {
+        "nearest" => Ok(FilterType::Nearest),
+        "bilinear" => Ok(FilterType::Triangle),
+        "lanczos" => Ok(FilterType::Lanczos3),
+        _ => Err(format!("unknown filter: {}", value)),
+    }
+}
+
+fn parse_color(value: &str) -> Result<Rgb<u8>, String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    let error = || format!("invalid color: {}", value);
+    if hex.len() != 6 {
+        return Err(error());
+    }
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or_else(error)
+    };
+    Ok(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+/// The crop that keeps a `crop_width` by `crop_height` region in the middle of the
+/// image, or `None` if the region is empty or doesn't fit. Odd leftover pixels are
+/// taken from the right and bottom.
+fn center_crop((width, height): (u32, u32), (crop_width, crop_height): (u32, u32)) -> Option<Crop> {
+    if crop_width == 0 || crop_height == 0 || crop_width > width || crop_height > height {
+        return None;
+    }
+    let left = (width - crop_width) / 2;
+    let top = (height - crop_height) / 2;
+    Some(Crop {
+        left,
+        right: width - crop_width - left,
+        top,
+        bottom: height - crop_height - top,
+    })
+}
+
+/// Check that a crop given on the command line keeps at least one pixel of the image.
+/// Not cropping at all is always fine, even for an empty image.
+fn check_crop(crop: Crop, (width, height): (u32, u32)) -> Result<Crop, String> {
+    let fits = |size: u32, a: u32, b: u32| matches!(a.checked_add(b), Some(sum) if sum < size);
+    if crop == Crop::default()
+        || (fits(width, crop.left, crop.right) && fits(height, crop.top, crop.bottom))
+    {
+        Ok(crop)
+    } else {
+        Err(format!(
+            "Cannot crop {} from a {}x{} image",
+            crop, width, height
+        ))
+    }
+}
+
+/// Clamp each side of a crop so that at least one pixel of the image is kept.
+fn clamp_crop(crop: Crop, (width, height): (u32, u32)) -> Crop {
+    let left = min(crop.left, width.saturating_sub(1));
+    let top = min(crop.top, height.saturating_sub(1));
+    Crop {
+        left,
+        right: min(crop.right, width.saturating_sub(left + 1)),
+        top,
+        bottom: min(crop.bottom, height.saturating_sub(top + 1)),
+    }
+}
+
+/// Shrink a crop to the rectangle between two corners, given in coordinates of the
+/// already cropped image. The corners may be in either order, and corners outside of
+/// the cropped image are moved onto its edge.
+fn bbox_to_crop(
+    (width, height): (u32, u32),
+    crop: Crop,
+    (x1, y1): (u32, u32),
+    (x2, y2): (u32, u32),
+) -> Crop {
+    let cropped_width = width - crop.left - crop.right;
+    let cropped_height = height - crop.top - crop.bottom;
+    let (x1, x2) = (min(x1, cropped_width), min(x2, cropped_width));
+    let (y1, y2) = (min(y1, cropped_height), min(y2, cropped_height));
+    clamp_crop(
+        Crop {
+            left: crop.left + min(x1, x2),
+            right: crop.right + cropped_width - max(x1, x2),
+            top: crop.top + min(y1, y2),
+            bottom: crop.bottom + cropped_height - max(y1, y2),
+        },
+        (width, height),
+    )
+}
+
+/// Bounding box of the connected region of pixels around `(x, y)` whose channels all
+/// differ from those of the starting pixel by at most `tolerance`. Coordinates are
+/// relative to the cropped image, and the returned corners are the top left pixel and
+/// one past the bottom right pixel, as taken by `bbox_to_crop`.
+fn region_bbox(
+    image: &RgbaImage,
+    crop: Crop,
+    (x, y): (u32, u32),
+    tolerance: u8,
+) -> ((u32, u32), (u32, u32)) {
+    let width = image.width() - crop.left - crop.right;
+    let height = image.height() - crop.top - crop.bottom;
+    let pixel = |x: u32, y: u32| image.get_pixel(x + crop.left, y + crop.top).0;
+    let seed = pixel(x, y);
+    let similar = |x: u32, y: u32| {
+        pixel(x, y)
+            .iter()
+            .zip(seed.iter())
+            .all(|(&a, &b)| (a as i16 - b as i16).abs() <= tolerance as i16)
+    };
+
+    let mut visited = vec![false; width as usize * height as usize];
+    let mut stack = vec![(x, y)];
+    visited[y as usize * width as usize + x as usize] = true;
+    let (mut x1, mut y1, mut x2, mut y2) = (x, y, x, y);
+    while let Some((x, y)) = stack.pop() {
+        x1 = min(x1, x);
+        y1 = min(y1, y);
+        x2 = max(x2, x);
+        y2 = max(y2, y);
+
+        let neighbors = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+        for (nx, ny) in neighbors {
+            if nx >= width || ny >= height {
+                continue;
+            }
+            let index = ny as usize * width as usize + nx as usize;
+            if !visited[index] && similar(nx, ny) {
+                visited[index] = true;
+                stack.push((nx, ny));
+            }
+        }
+    }
+    ((x1, y1), (x2 + 1, y2 + 1))
+}
+
+/// Like `bbox_to_crop`, but for corners with fractional coordinates.
+fn bbox_to_subpixel_crop(
+    (width, height): (u32, u32),
+    crop: Crop,
+    (x1, y1): (f64, f64),
+    (x2, y2): (f64, f64),
+) -> SubpixelCrop {
+    let cropped_width = (width - crop.left - crop.right) as f64;
+    let cropped_height = (height - crop.top - crop.bottom) as f64;
+    let (x1, x2) = (x1.min(cropped_width), x2.min(cropped_width));
+    let (y1, y2) = (y1.min(cropped_height), y2.min(cropped_height));
+    SubpixelCrop {
+        left: crop.left as f64 + x1.min(x2),
+        right: crop.right as f64 + cropped_width - x1.max(x2),
+        top: crop.top as f64 + y1.min(y2),
+        bottom: crop.bottom as f64 + cropped_height - y1.max(y2),
+    }
+}
+
+fn crop_image(image: DynamicImage, crop: Crop) -> DynamicImage {
+    // avoid copying the whole image when there is nothing to crop
+    if crop == Crop::default() {
+        return image;
+    }
     let width = image.width() - crop.left - crop.right;
     let height = image.height() - crop.top - crop.bottom;
-    image::imageops::crop(image, crop.left, crop.top, width, height).to_image()
+    image.crop_imm(crop.left, crop.top, width, height)
+}
+
+/// Crop to a region with fractional sides, resampling it with bilinear interpolation
+/// so that pixels only partly inside the region are blended rather than snapped. The
+/// result keeps the color type of the image, and has the size that the whole-pixel
+/// `rounded` crop would give.
+fn crop_subpixel(image: &DynamicImage, crop: SubpixelCrop, rounded: Crop) -> DynamicImage {
+    let region_width = image.width() as f64 - crop.left - crop.right;
+    let region_height = image.height() as f64 - crop.top - crop.bottom;
+    let width = image.width() - rounded.left - rounded.right;
+    let height = image.height() - rounded.top - rounded.bottom;
+
+    // only convert the region, with a pixel of margin for the interpolation
+    let x_start = (crop.left.floor() as u32).saturating_sub(1);
+    let y_start = (crop.top.floor() as u32).saturating_sub(1);
+    let x_end = min((crop.left + region_width).ceil() as u32 + 1, image.width());
+    let y_end = min((crop.top + region_height).ceil() as u32 + 1, image.height());
+    let source = image
+        .crop_imm(x_start, y_start, x_end - x_start, y_end - y_start)
+        .to_rgba32f();
+    let (source_width, source_height) = source.dimensions();
+
+    let sample = |x: f64, y: f64| {
+        let x = x.clamp(0.0, source_width.saturating_sub(1) as f64);
+        let y = y.clamp(0.0, source_height.saturating_sub(1) as f64);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = (
+            min(x0 + 1, source_width - 1),
+            min(y0 + 1, source_height - 1),
+        );
+        let (fx, fy) = ((x - x0 as f64) as f32, (y - y0 as f64) as f32);
+        let [a, b, c, d] = [
+            source.get_pixel(x0, y0).0,
+            source.get_pixel(x1, y0).0,
+            source.get_pixel(x0, y1).0,
+            source.get_pixel(x1, y1).0,
+        ];
+        let mut pixel = [0.0; 4];
+        for i in 0..4 {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            pixel[i] = top + (bottom - top) * fy;
+        }
+        Rgba(pixel)
+    };
+
+    // sample at the center of each output pixel
+    let resampled = Rgba32FImage::from_fn(width, height, |x, y| {
+        sample(
+            crop.left - x_start as f64 + (x as f64 + 0.5) * region_width / width as f64 - 0.5,
+            crop.top - y_start as f64 + (y as f64 + 0.5) * region_height / height as f64 - 0.5,
+        )
+    });
+    convert_color(DynamicImage::ImageRgba32F(resampled), image.color())
+}
+
+/// Composite the image over a solid background, dropping the alpha channel.
+fn flatten(image: &RgbaImage, background: Rgb<u8>) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let blend = |c: u8, bg: u8| {
+            let (c, bg, a) = (c as u32, bg as u32, a as u32);
+            ((c * a + bg * (255 - a) + 127) / 255) as u8
+        };
+        Rgb([
+            blend(r, background[0]),
+            blend(g, background[1]),
+            blend(b, background[2]),
+        ])
+    })
+}
+
+/// Like `flatten`, for 16-bit images.
+fn flatten16(
+    image: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+    background: Rgb<u8>,
+) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let blend = |c: u16, bg: u8| {
+            let (c, bg, a) = (c as u32, bg as u32 * 257, a as u32);
+            ((c * a + bg * (65535 - a) + 32767) / 65535) as u16
+        };
+        Rgb([
+            blend(r, background[0]),
+            blend(g, background[1]),
+            blend(b, background[2]),
+        ])
+    })
+}
+
+/// Whether the image has 16 bits per sample.
+fn is_16bit(image: &DynamicImage) -> bool {
+    matches!(
+        image.color(),
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16
+    )
+}
+
+/// Apply the rotation, flip, scaling, background, grayscale, and bit depth
+/// options to a cropped image.
+fn transform_output(image: DynamicImage, args: &PNGArgs) -> Result<DynamicImage, String> {
+    let (scaled_width, scaled_height) = output_size(image.dimensions(), args)?;
+
+    let image = match args.rotate {
+        Some(90) => image.rotate90(),
+        Some(180) => image.rotate180(),
+        Some(270) => image.rotate270(),
+        _ => image,
+    };
+
+    let image = match args.flip {
+        Some(Flip::Horizontal) => image.fliph(),
+        Some(Flip::Vertical) => image.flipv(),
+        None => image,
+    };
+
+    let image = if (scaled_width, scaled_height) != image.dimensions() {
+        if args.verbose {
+            eprintln!("scaled size: {}x{}", scaled_width, scaled_height);
+        }
+        image.resize_exact(
+            scaled_width,
+            scaled_height,
+            args.filter.unwrap_or(FilterType::Lanczos3),
+        )
+    } else {
+        image
+    };
+
+    let image = match args.background {
+        Some(background) if image.color().has_alpha() && is_16bit(&image) => {
+            DynamicImage::ImageRgb16(flatten16(&image.to_rgba16(), background))
+        }
+        Some(background) if image.color().has_alpha() => {
+            DynamicImage::ImageRgb8(flatten(&image.to_rgba8(), background))
+        }
+        _ => image,
+    };
+
+    let image = if args.grayscale && is_16bit(&image) {
+        DynamicImage::ImageLuma16(image.to_luma16())
+    } else if args.grayscale {
+        DynamicImage::ImageLuma8(image.to_luma8())
+    } else {
+        image
+    };
+
+    Ok(match args.output_bitdepth {
+        Some(depth) => convert_bit_depth(image, depth),
+        None => image,
+    })
+}
+
+/// The size of a cropped image after rotating and scaling it, without doing either.
+fn output_size((width, height): (u32, u32), args: &PNGArgs) -> Result<(u32, u32), String> {
+    if width == 0 || height == 0 {
+        return Err("Cannot save an empty image".to_string());
+    }
+
+    let (width, height) = match args.rotate {
+        Some(90 | 270) => (height, width),
+        _ => (width, height),
+    };
+    let (scaled_width, scaled_height) = match args.scale {
+        Some(Scale::Size(width, height)) => (width as f64, height as f64),
+        Some(Scale::Factor(scale)) => (width as f64 * scale, height as f64 * scale),
+        None => (width as f64, height as f64),
+    };
+    let scaled_width = (scaled_width * args.scale_x.unwrap_or(1.0)) as u32;
+    let scaled_height = (scaled_height * args.scale_y.unwrap_or(1.0)) as u32;
+
+    if scaled_width == 0 || scaled_height == 0 {
+        return Err(format!(
+            "Cannot scale to {}x{}: output must be at least 1x1",
+            scaled_width, scaled_height
+        ));
+    }
+    Ok((scaled_width, scaled_height))
+}
+
+/// What `--dry-run` prints for a cropped image.
+fn dry_run_report(image: &DynamicImage, args: &PNGArgs) -> Result<String, String> {
+    let (width, height) = output_size(image.dimensions(), args)?;
+    Ok(format!(
+        "size: {}x{}\noutput: {}\n",
+        width,
+        height,
+        args.output.as_deref().unwrap_or("none")
+    ))
+}
+
+/// Raise the color samples of an 8 or 16 bit image to a power, through a lookup table.
+/// Alpha is left alone.
+fn apply_gamma(mut image: DynamicImage, exponent: f64) -> DynamicImage {
+    fn table<T>(max: f64, exponent: f64, convert: impl Fn(f64) -> T) -> Vec<T> {
+        (0..=max as usize)
+            .map(|v| convert((v as f64 / max).powf(exponent) * max))
+            .collect()
+    }
+    fn correct<T: Copy + Into<usize>>(samples: &mut [T], channels: usize, table: &[T]) {
+        let colors = match channels {
+            2 | 4 => channels - 1,
+            _ => channels,
+        };
+        for pixel in samples.chunks_exact_mut(channels) {
+            for sample in &mut pixel[..colors] {
+                *sample = table[(*sample).into()];
+            }
+        }
+    }
+
+    let channels = image.color().channel_count() as usize;
+    if image.color().bytes_per_pixel() as usize == channels {
+        let table = table(255.0, exponent, |v| v.round() as u8);
+        match &mut image {
+            DynamicImage::ImageLuma8(buffer) => correct(buffer, channels, &table),
+            DynamicImage::ImageLumaA8(buffer) => correct(buffer, channels, &table),
+            DynamicImage::ImageRgb8(buffer) => correct(buffer, channels, &table),
+            DynamicImage::ImageRgba8(buffer) => correct(buffer, channels, &table),
+            _ => {}
+        }
+    } else {
+        let table = table(65535.0, exponent, |v| v.round() as u16);
+        match &mut image {
+            DynamicImage::ImageLuma16(buffer) => correct(buffer, channels, &table),
+            DynamicImage::ImageLumaA16(buffer) => correct(buffer, channels, &table),
+            DynamicImage::ImageRgb16(buffer) => correct(buffer, channels, &table),
+            DynamicImage::ImageRgba16(buffer) => correct(buffer, channels, &table),
+            _ => {}
+        }
+    }
+    image
+}
+
+/// Convert to 8 or 16 bits per sample, keeping the channels. Samples are rescaled to
+/// the new range rather than truncated.
+fn convert_bit_depth(image: DynamicImage, depth: u8) -> DynamicImage {
+    let color = match (depth, image.color().channel_count()) {
+        (8, 1) => ColorType::L8,
+        (8, 2) => ColorType::La8,
+        (8, 3) => ColorType::Rgb8,
+        (8, _) => ColorType::Rgba8,
+        (_, 1) => ColorType::L16,
+        (_, 2) => ColorType::La16,
+        (_, 3) => ColorType::Rgb16,
+        (_, _) => ColorType::Rgba16,
+    };
+    convert_color(image, color)
+}
+
+/// Convert to one of the 8 or 16 bit color types. Other color types are left as is.
+fn convert_color(image: DynamicImage, color: ColorType) -> DynamicImage {
+    if image.color() == color {
+        return image;
+    }
+    match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(image.to_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(image.to_rgb8()),
+        ColorType::Rgba8 => DynamicImage::ImageRgba8(image.to_rgba8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(image.to_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(image.to_rgb16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(image.to_rgba16()),
+        _ => image,
+    }
+}
+
+/// Transform a cropped image according to the command line options and save it.
+fn write_output(image: DynamicImage, path: &str, args: &PNGArgs) -> Result<(), String> {
+    let image = transform_output(image, args)?;
+
+    let start = Instant::now();
+    save_image(&image, path, args.quality.unwrap_or(75)).map_err(|e| e.to_string())?;
+    if args.verbose {
+        eprintln!("encode time: {:.2?}", start.elapsed());
+    }
+    Ok(())
+}
+
+/// The sizes printed by `--verbose`.
+fn size_report((width, height): (u32, u32), (cropped_width, cropped_height): (u32, u32)) -> String {
+    format!(
+        "original size: {}x{}\ncropped size: {}x{}\n",
+        width, height, cropped_width, cropped_height
+    )
+}
+
+/// Insert a suffix before the extension of a path, e.g. `photo-crop.png` for
+/// `photo.png` and the suffix `crop`.
+fn suffixed_path(path: &str, suffix: &str) -> String {
+    let path = Path::new(path);
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push("-");
+    name.push(suffix);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// The first path numbered `start` or higher, as in `photo-3.png`, for which `exists`
+/// is false, along with its number. This keeps earlier sessions' crops from being
+/// overwritten.
+fn numbered_path(base: &str, start: u32, exists: impl Fn(&str) -> bool) -> (u32, String) {
+    (start..)
+        .map(|number| (number, suffixed_path(base, &number.to_string())))
+        .find(|(_, path)| !exists(path))
+        .expect("ran out of file numbers")
+}
+
+fn save_image(image: &DynamicImage, path: &str, quality: u8) -> ImageResult<()> {
+    match ImageFormat::from_path(path)? {
+        ImageFormat::Jpeg => {
+            let mut file = BufWriter::new(File::create(path)?);
+            let format = ImageOutputFormat::Jpeg(quality);
+            // JPEG only stores 8-bit samples
+            let color = image.color();
+            if color.bytes_per_pixel() == color.channel_count() {
+                image.write_to(&mut file, format)
+            } else if color.channel_count() <= 2 {
+                DynamicImage::ImageLuma8(image.to_luma8()).write_to(&mut file, format)
+            } else {
+                DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut file, format)
+            }
+        }
+        _ => image.save(path),
+    }
 }
 
 fn main() {
@@ -121,42 +844,153 @@ fn main() {
         }
     }
 
-    let mut image: RgbaImage = match image::open(&args.input) {
-        Ok(im) => im.into_rgba8(),
+    if args.list_info || args.info_json {
+        match ImageInfo::read(&args.input) {
+            Ok(info) if args.info_json => info.print_json(),
+            Ok(info) => info.print(),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.multi && args.quiet {
+        eprintln!("Cannot specify both --multi and --quiet");
+        exit(1);
+    }
+
+    if matches!(args.scale, Some(Scale::Size(_, _)))
+        && (args.scale_x.is_some() || args.scale_y.is_some())
+    {
+        eprintln!("Cannot specify both --scale WxH and --scale-x or --scale-y");
+        exit(1);
+    }
+
+    if matches!(args.quality, Some(quality) if quality > 100) {
+        eprintln!("Quality must be between 0 and 100");
+        exit(1);
+    }
+
+    let start = Instant::now();
+    let mut image = match image::open(&args.input) {
+        Ok(im) => im,
         Err(e) => {
             eprintln!("{}", e);
             exit(1);
         }
     };
+    if args.verbose {
+        eprintln!("decode time: {:.2?}", start.elapsed());
+    }
+    let original_size = image.dimensions();
+    if is_apng(&args.input) {
+        eprintln!("{} is animated, only its default image is used", args.input);
+    }
+    // correct the whole image once, so that the viewer shows what will be saved
+    if let Some(display_gamma) = args.apply_gamma {
+        if let Some(gamma) = file_gamma(&args.input) {
+            image = apply_gamma(image, 1.0 / (gamma * display_gamma));
+        }
+    }
 
-    image = crop_image(
-        &mut image,
-        Crop {
+    let crop = match args.crop_center {
+        Some(size) => {
+            if args.crop_left.is_some()
+                || args.crop_right.is_some()
+                || args.crop_top.is_some()
+                || args.crop_bottom.is_some()
+                || args.crop_spec.is_some()
+            {
+                eprintln!("Cannot specify both --crop-center and an edge crop");
+                exit(1);
+            }
+            match center_crop(image.dimensions(), size) {
+                Some(crop) => crop,
+                None => {
+                    eprintln!(
+                        "Cannot crop a {}x{} image to {}x{}",
+                        image.width(),
+                        image.height(),
+                        size.0,
+                        size.1
+                    );
+                    exit(1);
+                }
+            }
+        }
+        None => args.crop_spec.unwrap_or_default().compose(Crop {
             left: args.crop_left.unwrap_or(0),
             right: args.crop_right.unwrap_or(0),
             top: args.crop_top.unwrap_or(0),
             bottom: args.crop_bottom.unwrap_or(0),
-        },
-    );
+        }),
+    };
+    let crop = match check_crop(crop, image.dimensions()) {
+        Ok(crop) => crop,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    image = crop_image(image, crop);
+
+    if args.dry_run {
+        match dry_run_report(&image, &args) {
+            Ok(report) => print!("{}", report),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
 
     let output_image = if args.quiet {
         image
     } else {
         let surface = GlfwSurface::new(|glfw| {
-            let (mut window, events) = glfw.with_primary_monitor(|glfw, mon| {
-                let (width, height) = mon
-                    .and_then(|m| m.get_video_mode())
-                    .map_or((500, 500), |v| (v.width / 2, v.height / 2));
-                glfw.create_window(width, height, "motsu", WindowMode::Windowed)
-                    .ok_or(GlfwSurfaceError::UserError("Couldn't Open Window"))
-            })?;
+            let (mut window, events) = glfw
+                .with_connected_monitors(|glfw, monitors| {
+                    // the primary monitor is always the first one
+                    let monitor = match args.monitor {
+                        Some(n) if n < monitors.len() => monitors.get(n),
+                        Some(n) => {
+                            eprintln!("No monitor {}, using the primary monitor", n);
+                            monitors.first()
+                        }
+                        None => monitors.first(),
+                    };
+                    let (width, height) = monitor
+                        .and_then(|m| m.get_video_mode())
+                        .map_or((500, 500), |v| (v.width / 2, v.height / 2));
+                    let (mut window, events) =
+                        glfw.create_window(width, height, "motsu", WindowMode::Windowed)?;
+                    // leave placement to the window manager unless a monitor was chosen
+                    if let (Some(_), Some(monitor)) = (args.monitor, monitor) {
+                        let (x, y) = monitor.get_pos();
+                        window.set_pos(x + width as i32 / 2, y + height as i32 / 2);
+                    }
+                    Some((window, events))
+                })
+                .ok_or(GlfwSurfaceError::UserError("Couldn't Open Window"))?;
             window.make_current();
             window.set_all_polling(true);
             glfw.set_swap_interval(SwapInterval::Sync(1));
             Ok((window, events))
         });
         match surface {
-            Ok(surface) => main_loop(surface, image),
+            Ok(surface) => {
+                let (viewer_crop, output_image) =
+                    main_loop(surface, image, crop, &Config::load(), &args);
+                // print the crop relative to the original image so it can be replayed; in
+                // multi mode each save prints its own
+                if !args.multi {
+                    println!("{}", crop.compose(viewer_crop));
+                }
+                output_image
+            }
             Err(e) => {
                 eprintln!("cannot create graphics surface:\n{}", e);
                 exit(1);
@@ -164,57 +998,112 @@ fn main() {
         }
     };
 
-    let output_image = if let Some(scale) = args.scale {
-        let width = output_image.width() as f64;
-        let height = output_image.height() as f64;
-        image::imageops::resize(
-            &output_image,
-            (width * scale) as u32,
-            (height * scale) as u32,
-            image::imageops::FilterType::Lanczos3,
-        )
-    } else {
-        output_image
-    };
+    if args.verbose {
+        eprint!("{}", size_report(original_size, output_image.dimensions()));
+    }
+
+    // in multi mode, every crop was already saved from the viewer
+    if args.multi {
+        return;
+    }
 
-    if let Some(outfile) = args.output {
-        if let Err(e) = output_image.save(outfile) {
+    if let Some(outfile) = &args.output {
+        if let Err(e) = write_output(output_image, outfile, &args) {
             eprintln!("{}", e);
             exit(1);
         }
     }
 }
 
+/// Mapping between framebuffer pixels and pixels of the cropped image. The image is
+/// drawn centered in the framebuffer, scaled to fit while keeping its aspect ratio.
+#[derive(Clone, Copy, Debug)]
+struct DisplayTransform {
+    image_size: (f64, f64),
+    display_size: (f64, f64),
+    offset: (f64, f64),
+}
+
+impl DisplayTransform {
+    /// Images smaller than the framebuffer are only enlarged if `upscale` is set.
+    fn new(
+        (image_width, image_height): (u32, u32),
+        (buffer_width, buffer_height): (u32, u32),
+        upscale: bool,
+    ) -> Self {
+        let fit = f64::min(
+            buffer_width as f64 / max(image_width, 1) as f64,
+            buffer_height as f64 / max(image_height, 1) as f64,
+        );
+        let scale = if upscale { fit } else { fit.min(1.0) };
+        let display_size = (image_width as f64 * scale, image_height as f64 * scale);
+        DisplayTransform {
+            image_size: (image_width as f64, image_height as f64),
+            display_size,
+            offset: (
+                (buffer_width as f64 - display_size.0) / 2.0,
+                (buffer_height as f64 - display_size.1) / 2.0,
+            ),
+        }
+    }
+
+    /// The pixel under a framebuffer position, or `None` if the position is outside of
+    /// the image. Positions on the right and bottom edges belong to the last pixel.
+    fn pixel_position(&self, position: (f64, f64)) -> Option<(u32, u32)> {
+        let (x, y) = self.subpixel_position(position)?;
+        let pixel = |v: f64, size: f64| min(v.floor() as u32, (size as u32).saturating_sub(1));
+        Some((pixel(x, self.image_size.0), pixel(y, self.image_size.1)))
+    }
+
+    /// The image position under a framebuffer position, or `None` if the position is
+    /// outside of the image. Positions on the right and bottom edges map to the image
+    /// width and height.
+    fn subpixel_position(&self, (x, y): (f64, f64)) -> Option<(f64, f64)> {
+        let x = (x - self.offset.0) / self.display_size.0;
+        let y = (y - self.offset.1) / self.display_size.1;
+        if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+            return None;
+        }
+        Some((x * self.image_size.0, y * self.image_size.1))
+    }
+
+    /// Like `subpixel_position`, but positions outside of the image are moved onto its
+    /// nearest edge.
+    fn clamped_position(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        // an empty image has no size to divide by
+        let clamp = |v: f64| if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) };
+        let x = clamp((x - self.offset.0) / self.display_size.0);
+        let y = clamp((y - self.offset.1) / self.display_size.1);
+        (x * self.image_size.0, y * self.image_size.1)
+    }
+}
+
 fn calculate_vertices(
     image_width: u32,
     image_height: u32,
     buffer_width: u32,
     buffer_height: u32,
     crop: Crop,
+    upscale: bool,
 ) -> [Vertex; 4] {
+    let transform = DisplayTransform::new(
+        (
+            image_width - crop.left - crop.right,
+            image_height - crop.top - crop.bottom,
+        ),
+        (buffer_width, buffer_height),
+        upscale,
+    );
+    let width = (transform.display_size.0 / max(buffer_width, 1) as f64) as f32;
+    let height = (transform.display_size.1 / max(buffer_height, 1) as f64) as f32;
+
     let crop_left: f32 = crop.left as f32;
     let crop_right: f32 = crop.right as f32;
     let crop_top: f32 = crop.top as f32;
     let crop_bottom: f32 = crop.bottom as f32;
-    let image_width: f32 = image_width as f32;
-    let image_height: f32 = image_height as f32;
-    let buffer_width: f32 = buffer_width as f32;
-    let buffer_height: f32 = buffer_height as f32;
-
-    let cropped_width = image_width - crop_left - crop_right;
-    let cropped_height = image_height - crop_top - crop_bottom;
-
-    let width = if cropped_width <= buffer_width {
-        cropped_width / buffer_width
-    } else {
-        1.0
-    };
-
-    let height = if cropped_height <= buffer_height {
-        cropped_height / buffer_height
-    } else {
-        1.0
-    };
+    // an empty image or a minimized window would otherwise give NaN vertices
+    let image_width: f32 = max(image_width, 1) as f32;
+    let image_height: f32 = max(image_height, 1) as f32;
 
     let cl = CropLeft::new(crop_left / image_width);
     let cr = CropRight::new(1.0 - crop_right / image_width);
@@ -233,6 +1122,9 @@ fn make_texture(
     surface: &mut GlfwSurface,
     image: &RgbaImage,
 ) -> Texture<GlfwBackend, Dim2, NormRGBA8UI> {
+    // textures can't be empty, so an empty image is shown as one transparent pixel
+    let blank = RgbaImage::new(1, 1);
+    let image = if image.is_empty() { &blank } else { image };
     let tex = surface
         .context
         .new_texture_raw(
@@ -251,8 +1143,9 @@ fn make_tess(
     surface: &mut GlfwSurface,
     image: &RgbaImage,
     crop: Crop,
+    upscale: bool,
 ) -> Tess<GlfwBackend, Vertex> {
-    let (width, height) = surface.context.window.get_size();
+    let (width, height) = surface.context.window.get_framebuffer_size();
     TessBuilder::new(&mut surface.context)
         .set_vertices(calculate_vertices(
             image.width(),
@@ -260,26 +1153,144 @@ fn make_tess(
             width as u32,
             height as u32,
             crop,
+            upscale,
         ))
         .set_mode(Mode::TriangleFan)
         .build()
         .unwrap()
 }
 
-fn calculate_delta(modifiers: Modifiers) -> u32 {
+/// Overwrite the vertices of an existing tess, so that redrawing after a crop or a
+/// resize doesn't allocate a new vertex array and buffer.
+fn update_tess(
+    surface: &GlfwSurface,
+    tess: &mut Tess<GlfwBackend, Vertex>,
+    image: &RgbaImage,
+    crop: Crop,
+    upscale: bool,
+) {
+    let (width, height) = surface.context.window.get_framebuffer_size();
+    let vertices = calculate_vertices(
+        image.width(),
+        image.height(),
+        width as u32,
+        height as u32,
+        crop,
+        upscale,
+    );
+    tess.vertices_mut()
+        .expect("luminance tess mapping failed")
+        .copy_from_slice(&vertices);
+}
+
+/// Ratio of framebuffer pixels to window coordinates, which differ on HiDPI displays.
+fn framebuffer_scale(window: &glfw::Window) -> (f64, f64) {
+    let (width, height) = window.get_size();
+    let (fb_width, fb_height) = window.get_framebuffer_size();
+    (
+        fb_width as f64 / max(width, 1) as f64,
+        fb_height as f64 / max(height, 1) as f64,
+    )
+}
+
+/// Move the kept region of the image to its middle without changing its size.
+fn recenter_crop(crop: Crop, (width, height): (u32, u32)) -> Crop {
+    let size = (
+        width - crop.left - crop.right,
+        height - crop.top - crop.bottom,
+    );
+    center_crop((width, height), size).unwrap_or(crop)
+}
+
+/// Move the kept region of the image without changing its size. The region stops at
+/// the edges of the image.
+fn pan_crop(crop: Crop, (dx, dy): (i64, i64)) -> Crop {
+    let dx = dx.clamp(-(crop.left as i64), crop.right as i64);
+    let dy = dy.clamp(-(crop.top as i64), crop.bottom as i64);
+    Crop {
+        left: (crop.left as i64 + dx) as u32,
+        right: (crop.right as i64 - dx) as u32,
+        top: (crop.top as i64 + dy) as u32,
+        bottom: (crop.bottom as i64 - dy) as u32,
+    }
+}
+
+/// Modification times seen by `--watch`. A new modification time is only acted on
+/// once it is seen twice in a row, so that a file still being written isn't loaded.
+#[derive(Debug, PartialEq)]
+struct WatchedFile {
+    modified: Option<SystemTime>,
+    pending: Option<SystemTime>,
+}
+
+impl WatchedFile {
+    fn new(modified: Option<SystemTime>) -> Self {
+        WatchedFile {
+            modified,
+            pending: None,
+        }
+    }
+
+    /// Record the current modification time of the file, returning whether it should
+    /// be reloaded.
+    fn should_reload(&mut self, current: Option<SystemTime>) -> bool {
+        // the file may be missing for a moment while another program saves it
+        if current.is_none() || current == self.modified {
+            self.pending = None;
+            false
+        } else if current != self.pending {
+            self.pending = current;
+            false
+        } else {
+            *self = WatchedFile::new(current);
+            true
+        }
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn calculate_delta(config: &Config, modifiers: Modifiers) -> u32 {
     if modifiers.contains(Modifiers::Control) {
-        10
+        config.fast_nudge
     } else {
-        1
+        config.nudge
     }
 }
 
-fn main_loop(mut surface: GlfwSurface, mut image: RgbaImage) -> RgbaImage {
+fn main_loop(
+    mut surface: GlfwSurface,
+    mut source: DynamicImage,
+    base_crop: Crop,
+    config: &Config,
+    args: &PNGArgs,
+) -> (Crop, DynamicImage) {
+    // the viewer always works in RGBA, but saved crops keep the color type of the source
+    let mut image = source.to_rgba8();
+
     // setup for loop
+    let upscale = !args.no_resize_small;
+    let file_name = Path::new(&args.input).file_name().map_or_else(
+        || args.input.clone(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let keys = config.keys;
     let mut redraw = true;
     let mut crop: Crop = Default::default();
-    let mut mouse_position: (u32, u32) = (0, 0);
-    let mut mouse_click: Option<(u32, u32)> = None;
+    let mut mouse_position: (f64, f64) = (0.0, 0.0);
+    let mut mouse_click: Option<(f64, f64)> = None;
+    // number of the last file saved in multi mode
+    let mut crop_number = 0;
+    let mut wand = false;
+    // whether the pixel under the cursor is shown in the title
+    let mut show_cursor = false;
+    let mut tolerance: u8 = 16;
+    // the exact mouse selection, along with the crop it was rounded to
+    let mut subpixel_crop: Option<(Crop, SubpixelCrop)> = None;
+    let mut watched = WatchedFile::new(modified_time(&args.input));
+    let mut last_check = Instant::now();
 
     let mut program = surface
         .context
@@ -295,124 +1306,263 @@ fn main_loop(mut surface: GlfwSurface, mut image: RgbaImage) -> RgbaImage {
     let pipeline_st = PipelineState::default().set_clear_color([1.0, 1.0, 1.0, 1.0]);
 
     let mut tex = make_texture(&mut surface, &image);
+    let mut tess = make_tess(&mut surface, &image, crop, upscale);
 
     'app: loop {
         surface.context.window.glfw.poll_events();
+
+        if args.watch && last_check.elapsed() >= WATCH_INTERVAL {
+            last_check = Instant::now();
+            if watched.should_reload(modified_time(&args.input)) {
+                match image::open(&args.input) {
+                    Ok(reloaded) => {
+                        let dimensions = reloaded.dimensions();
+                        source = crop_image(reloaded, clamp_crop(base_crop, dimensions));
+                        image = source.to_rgba8();
+                        tex = make_texture(&mut surface, &image);
+                        crop = clamp_crop(crop, image.dimensions());
+                        subpixel_crop = None;
+                        mouse_click = None;
+                        redraw = true;
+                    }
+                    Err(e) => eprintln!("cannot reload {}: {}", args.input, e),
+                }
+            }
+        }
         for (_, event) in surface.events_rx.try_iter() {
             // Nothing needs to happen on key release
             if let WindowEvent::Key(_, _, Action::Release, _) = event {
                 continue;
             }
 
+            let mut resized = false;
             match event {
-                WindowEvent::Close | WindowEvent::Key(Key::Escape | Key::Q, _, _, _) => break 'app,
-                WindowEvent::Pos(_, _) | WindowEvent::Size(_, _) | WindowEvent::Focus(_) => {
+                WindowEvent::Close => break 'app,
+                WindowEvent::Key(key, _, _, _) if key == Key::Escape || key == keys.quit => {
+                    break 'app
+                }
+                WindowEvent::Pos(_, _)
+                | WindowEvent::Size(_, _)
+                | WindowEvent::FramebufferSize(_, _)
+                | WindowEvent::ContentScale(_, _)
+                | WindowEvent::Focus(_) => {
                     redraw = true;
                 }
-                WindowEvent::Key(Key::K | Key::Up, _, _, modifiers) => {
-                    let delta = calculate_delta(modifiers);
+                WindowEvent::Key(key, _, _, modifiers) if key == keys.up => {
+                    let delta = calculate_delta(config, modifiers);
                     if modifiers.contains(Modifiers::Shift) {
                         crop.top -= min(delta, crop.top);
                     } else {
-                        crop.bottom += min(delta, image.height() - crop.top - crop.bottom - 1);
+                        crop.bottom += min(
+                            delta,
+                            (image.height() - crop.top - crop.bottom).saturating_sub(1),
+                        );
                     }
+                    resized = true;
                     redraw = true;
                 }
-                WindowEvent::Key(Key::J | Key::Down, _, _, modifiers) => {
-                    let delta = calculate_delta(modifiers);
+                WindowEvent::Key(key, _, _, modifiers) if key == keys.down => {
+                    let delta = calculate_delta(config, modifiers);
                     if modifiers.contains(Modifiers::Shift) {
                         crop.bottom -= min(delta, crop.bottom);
                     } else {
-                        crop.top += min(delta, image.height() - crop.top - crop.bottom - 1);
+                        crop.top += min(
+                            delta,
+                            (image.height() - crop.top - crop.bottom).saturating_sub(1),
+                        );
                     }
+                    resized = true;
                     redraw = true;
                 }
-                WindowEvent::Key(Key::H | Key::Left, _, _, modifiers) => {
-                    let delta = calculate_delta(modifiers);
+                WindowEvent::Key(key, _, _, modifiers) if key == keys.left => {
+                    let delta = calculate_delta(config, modifiers);
                     if modifiers.contains(Modifiers::Shift) {
                         crop.left -= min(delta, crop.left);
                     } else {
-                        crop.right += min(delta, image.width() - crop.left - crop.right - 1);
+                        crop.right += min(
+                            delta,
+                            (image.width() - crop.left - crop.right).saturating_sub(1),
+                        );
                     }
+                    resized = true;
                     redraw = true;
                 }
-                WindowEvent::Key(Key::L | Key::Right, _, _, modifiers) => {
-                    let delta = calculate_delta(modifiers);
+                WindowEvent::Key(key, _, _, modifiers) if key == keys.right => {
+                    let delta = calculate_delta(config, modifiers);
                     if modifiers.contains(Modifiers::Shift) {
                         crop.right -= min(delta, crop.right);
                     } else {
-                        crop.left += min(delta, image.width() - crop.left - crop.right - 1);
+                        crop.left += min(
+                            delta,
+                            (image.width() - crop.left - crop.right).saturating_sub(1),
+                        );
                     }
+                    resized = true;
                     redraw = true;
                 }
-                WindowEvent::Key(Key::R, _, Action::Press, _) => {
+                WindowEvent::Key(
+                    key @ (Key::Up | Key::Down | Key::Left | Key::Right),
+                    _,
+                    _,
+                    modifiers,
+                ) => {
+                    let delta = calculate_delta(config, modifiers) as i64;
+                    let offset = match key {
+                        Key::Up => (0, -delta),
+                        Key::Down => (0, delta),
+                        Key::Left => (-delta, 0),
+                        _ => (delta, 0),
+                    };
+                    crop = pan_crop(crop, offset);
+                    redraw = true;
+                }
+                WindowEvent::Key(key, _, Action::Press, _) if key == keys.reset => {
                     crop = Default::default();
                     mouse_click = None;
                     redraw = true;
                 }
+                WindowEvent::Key(key, _, Action::Press, _) if key == keys.save => {
+                    let (number, path) = if args.multi {
+                        let base = args.output.as_deref().unwrap_or(&args.input);
+                        numbered_path(base, crop_number + 1, |path| Path::new(path).exists())
+                    } else {
+                        let path = args.output.clone();
+                        (
+                            0,
+                            path.unwrap_or_else(|| suffixed_path(&args.input, "crop")),
+                        )
+                    };
+                    let output = match subpixel_crop {
+                        Some((rounded, exact)) if rounded == crop => {
+                            crop_subpixel(&source, exact, crop)
+                        }
+                        _ => crop_image(source.clone(), crop),
+                    };
+                    match write_output(output, &path, args) {
+                        Ok(()) if args.multi => {
+                            println!("Saved {} ({})", path, base_crop.compose(crop))
+                        }
+                        Ok(()) => println!("Saved {}", path),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            continue;
+                        }
+                    }
+                    // saving over the input shouldn't reload it and crop it again
+                    if Path::new(&path) == Path::new(&args.input) {
+                        watched = WatchedFile::new(modified_time(&args.input));
+                    }
+                    if args.multi {
+                        crop_number = number;
+                        crop = Default::default();
+                        mouse_click = None;
+                        redraw = true;
+                    }
+                }
+                WindowEvent::Key(key, _, Action::Press, _) if key == keys.wand => {
+                    wand = !wand;
+                    mouse_click = None;
+                    redraw = true;
+                }
+                WindowEvent::Key(key, _, Action::Press, _) if key == keys.cursor => {
+                    show_cursor = !show_cursor;
+                    redraw = true;
+                }
+                WindowEvent::Key(Key::Equal | Key::KpAdd, _, _, _) if wand => {
+                    tolerance = tolerance.saturating_add(8);
+                    redraw = true;
+                }
+                WindowEvent::Key(Key::Minus | Key::KpSubtract, _, _, _) if wand => {
+                    tolerance = tolerance.saturating_sub(8);
+                    redraw = true;
+                }
                 WindowEvent::CursorPos(x, y) => {
-                    mouse_position = (x as u32, y as u32);
+                    // cursor positions are in window coordinates, but the image is laid out in
+                    // framebuffer pixels
+                    let (scale_x, scale_y) = framebuffer_scale(&surface.context.window);
+                    mouse_position = (x * scale_x, y * scale_y);
+                    if show_cursor {
+                        redraw = true;
+                    }
                 }
                 WindowEvent::MouseButton(MouseButton::Button1, Action::Press, _) => {
-                    let (width, height) = surface.context.window.get_size();
-                    let im_width = (image.width() - crop.left - crop.right) as i32;
-                    let im_height = (image.height() - crop.top - crop.bottom) as i32;
-                    let disp_width = min(im_width, width);
-                    let disp_height = min(im_height, height);
+                    let (width, height) = surface.context.window.get_framebuffer_size();
+                    let cropped_width = image.width() - crop.left - crop.right;
+                    let cropped_height = image.height() - crop.top - crop.bottom;
+                    let transform = DisplayTransform::new(
+                        (cropped_width, cropped_height),
+                        (width as u32, height as u32),
+                        upscale,
+                    );
+                    if wand {
+                        if let Some(position) = transform.pixel_position(mouse_position) {
+                            let (p1, p2) = region_bbox(&image, crop, position, tolerance);
+                            crop = bbox_to_crop(image.dimensions(), crop, p1, p2);
+                            redraw = true;
+                        }
+                        continue;
+                    }
                     match mouse_click {
                         None => mouse_click = Some(mouse_position),
                         Some(mc) => {
                             if mc == mouse_position {
                                 continue;
                             }
-                            let x1: i32 = mc.0 as i32 - width / 2 + disp_width / 2;
-                            let y1: i32 = mc.1 as i32 - height / 2 + disp_height / 2;
-                            let x2: i32 = mouse_position.0 as i32 - width / 2 + disp_width / 2;
-                            let y2: i32 = mouse_position.1 as i32 - height / 2 + disp_height / 2;
-
-                            if x1 < 0
-                                || x2 < 0
-                                || y1 < 0
-                                || y2 < 0
-                                || x1 > disp_width
-                                || x2 > disp_width
-                                || y1 > disp_height
-                                || y2 > disp_height
-                            {
-                                mouse_click = None;
-                                continue;
-                            }
-
-                            let (x1, x2) = if width < im_width {
-                                (x1 * im_width / width, x2 * im_width / width)
-                            } else {
-                                (x1, x2)
-                            };
-
-                            let (y1, y2) = if height < im_height {
-                                (y1 * im_height / height, y2 * im_height / height)
-                            } else {
-                                (y1, y2)
-                            };
-
-                            crop.left += min(x1, x2) as u32;
-                            crop.right += (im_width - max(x1, x2)) as u32;
-                            crop.top += min(y1, y2) as u32;
-                            crop.bottom += (im_height - max(y1, y2)) as u32;
                             mouse_click = None;
+
+                            // clicks beside the image select up to its edge
+                            let p1 = transform.clamped_position(mc);
+                            let p2 = transform.clamped_position(mouse_position);
+                            let exact = bbox_to_subpixel_crop(image.dimensions(), crop, p1, p2);
+                            let round = |(x, y): (f64, f64)| (x.round() as u32, y.round() as u32);
+                            crop = bbox_to_crop(image.dimensions(), crop, round(p1), round(p2));
+                            if args.antialias_crop {
+                                subpixel_crop = Some((crop, exact));
+                            }
                             redraw = true;
                         }
                     }
                 }
                 _ => {}
             }
+
+            crop = clamp_crop(crop, image.dimensions());
+            // in center mode, resizing moves the opposite edge along with the one nudged
+            if resized && args.center {
+                crop = recenter_crop(crop, image.dimensions());
+            }
         }
 
         if redraw {
             let back_buffer = surface.context.back_buffer().unwrap();
-            let tess = make_tess(&mut surface, &image, crop);
+            update_tess(&surface, &mut tess, &image, crop, upscale);
             redraw = false;
 
+            let cropped_size = (
+                image.width() - crop.left - crop.right,
+                image.height() - crop.top - crop.bottom,
+            );
+            let mode = if wand {
+                format!(" (wand, tolerance {})", tolerance)
+            } else {
+                String::new()
+            };
+            let cursor = if show_cursor {
+                let (width, height) = surface.context.window.get_framebuffer_size();
+                let transform =
+                    DisplayTransform::new(cropped_size, (width as u32, height as u32), upscale);
+                match transform.pixel_position(mouse_position) {
+                    Some((x, y)) => format!(" ({}, {})", x + crop.left, y + crop.top),
+                    None => " (-, -)".to_string(),
+                }
+            } else {
+                String::new()
+            };
+            surface.context.window.set_title(&format!(
+                "motsu — {} [{}x{}]{}{}",
+                file_name, cropped_size.0, cropped_size.1, mode, cursor
+            ));
+
             surface
                 .context
                 .new_pipeline_gate()
@@ -428,5 +1578,862 @@ fn main_loop(mut surface: GlfwSurface, mut image: RgbaImage) -> RgbaImage {
         }
     }
 
-    crop_image(&mut image, crop)
+    // the exact selection only applies if the crop wasn't changed after making it
+    let output = match subpixel_crop {
+        Some((rounded, exact)) if rounded == crop => crop_subpixel(&source, exact, crop),
+        _ => crop_image(source, crop),
+    };
+    (crop, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{Luma, LumaA};
+
+    use std::env;
+    use std::process;
+
+    /// A path in the temporary directory that is unique to this test process.
+    fn temp_path(name: &str) -> String {
+        env::temp_dir()
+            .join(format!("motsu-test-{}-{}", process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn save_jpeg_keeps_dimensions() {
+        let path = temp_path("save.jpg");
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(30, 20));
+        save_image(&image, &path, 75).unwrap();
+        let saved = image::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.dimensions(), (30, 20));
+    }
+
+    #[test]
+    fn save_jpeg_of_gray16_is_gray() {
+        let path = temp_path("gray16.jpg");
+        let gray: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::new(12, 8);
+        save_image(&DynamicImage::ImageLuma16(gray), &path, 75).unwrap();
+        let saved = image::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.dimensions(), (12, 8));
+        assert_eq!(saved.color(), ColorType::L8);
+    }
+
+    #[test]
+    fn flatten_half_transparent_white_over_black() {
+        let image = RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 128]));
+        let flat = flatten(&image, Rgb([0, 0, 0]));
+        assert_eq!(flat.dimensions(), (2, 2));
+        assert!(flat.pixels().all(|p| p.0 == [128, 128, 128]));
+    }
+
+    #[test]
+    fn flatten_keeps_opaque_and_replaces_transparent() {
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, Rgba([10, 20, 30, 255]));
+        let flat = flatten(&image, Rgb([200, 100, 50]));
+        assert_eq!(flat.get_pixel(0, 0).0, [10, 20, 30]);
+        assert_eq!(flat.get_pixel(1, 0).0, [200, 100, 50]);
+    }
+
+    #[test]
+    fn check_crop_rejects_crops_that_keep_nothing() {
+        let crop = |left, right, top, bottom| Crop {
+            left,
+            right,
+            top,
+            bottom,
+        };
+        assert!(check_crop(crop(5000, 0, 0, 0), (100, 50)).is_err());
+        assert!(check_crop(crop(50, 50, 0, 0), (100, 50)).is_err());
+        assert!(check_crop(crop(0, 0, u32::MAX, 1), (100, 50)).is_err());
+        assert_eq!(
+            check_crop(crop(49, 50, 0, 49), (100, 50)),
+            Ok(crop(49, 50, 0, 49))
+        );
+        assert_eq!(check_crop(Crop::default(), (0, 0)), Ok(Crop::default()));
+    }
+
+    #[test]
+    fn clamp_crop_keeps_one_pixel() {
+        let crop = Crop {
+            left: 500,
+            right: 500,
+            top: 7,
+            bottom: 7,
+        };
+        let clamped = clamp_crop(crop, (100, 10));
+        assert_eq!(clamped.left, 99);
+        assert_eq!(clamped.right, 0);
+        assert_eq!(clamped.top, 7);
+        assert_eq!(clamped.bottom, 2);
+        assert_eq!(clamp_crop(crop, (0, 0)), Crop::default());
+    }
+
+    #[test]
+    fn bbox_to_crop_with_pathological_clicks() {
+        // both clicks on the same point still keep one pixel
+        let crop = bbox_to_crop((100, 50), Crop::default(), (30, 20), (30, 20));
+        assert_eq!(
+            (100 - crop.left - crop.right, 50 - crop.top - crop.bottom),
+            (1, 1)
+        );
+        // clicks far outside of the image select all of it
+        let crop = bbox_to_crop((100, 50), Crop::default(), (0, 0), (u32::MAX, u32::MAX));
+        assert_eq!(crop, Crop::default());
+        // a click on the far edge of an already cropped image
+        let inner = Crop {
+            left: 10,
+            right: 10,
+            top: 5,
+            bottom: 5,
+        };
+        let crop = bbox_to_crop((100, 50), inner, (80, 40), (40, 20));
+        assert_eq!(
+            crop,
+            Crop {
+                left: 50,
+                right: 10,
+                top: 25,
+                bottom: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn center_crop_offsets() {
+        assert_eq!(
+            center_crop((100, 100), (40, 60)),
+            Some(Crop {
+                left: 30,
+                right: 30,
+                top: 20,
+                bottom: 20,
+            })
+        );
+        // the odd leftover pixel goes to the right and bottom
+        assert_eq!(
+            center_crop((100, 100), (41, 59)),
+            Some(Crop {
+                left: 29,
+                right: 30,
+                top: 20,
+                bottom: 21,
+            })
+        );
+        assert_eq!(center_crop((100, 100), (100, 100)), Some(Crop::default()));
+    }
+
+    #[test]
+    fn center_crop_rejects_empty_and_oversized_regions() {
+        assert_eq!(center_crop((100, 100), (0, 0)), None);
+        assert_eq!(center_crop((100, 100), (40, 0)), None);
+        assert_eq!(center_crop((100, 100), (101, 40)), None);
+    }
+
+    #[test]
+    fn crop_image_without_crop_is_unchanged() {
+        let mut image = RgbaImage::new(3, 2);
+        image.put_pixel(2, 1, Rgba([1, 2, 3, 4]));
+        let image = DynamicImage::ImageRgba8(image);
+        assert_eq!(crop_image(image.clone(), Crop::default()), image);
+    }
+
+    #[test]
+    fn crop_image_keeps_the_inner_region() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(10, 10, |x, y| {
+            Rgba([x as u8, y as u8, 0, 255])
+        }));
+        let crop = Crop {
+            left: 2,
+            right: 3,
+            top: 1,
+            bottom: 4,
+        };
+        let cropped = crop_image(image, crop);
+        assert_eq!(cropped.dimensions(), (5, 5));
+        assert_eq!(cropped.get_pixel(0, 0).0, [2, 1, 0, 255]);
+    }
+
+    /// Parse command line options, followed by an input path.
+    fn args(options: &[&str]) -> PNGArgs {
+        let mut all = options.to_vec();
+        all.push("input.png");
+        PNGArgs::from_args(&["motsu"], &all).unwrap()
+    }
+
+    #[test]
+    fn rotate_90_swaps_dimensions() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(40, 30));
+        let rotated = transform_output(image, &args(&["--rotate", "90"])).unwrap();
+        assert_eq!(rotated.dimensions(), (30, 40));
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(40, 30));
+        let rotated = transform_output(image, &args(&["--rotate", "180"])).unwrap();
+        assert_eq!(rotated.dimensions(), (40, 30));
+    }
+
+    #[test]
+    fn display_transform_window_smaller_than_image() {
+        let transform = DisplayTransform::new((1000, 500), (500, 500), false);
+        assert_eq!(transform.display_size, (500.0, 250.0));
+        assert_eq!(transform.offset, (0.0, 125.0));
+        assert_eq!(
+            transform.subpixel_position((250.0, 250.0)),
+            Some((500.0, 250.0))
+        );
+        assert_eq!(
+            transform.subpixel_position((500.0, 375.0)),
+            Some((1000.0, 500.0))
+        );
+        assert_eq!(transform.subpixel_position((250.0, 100.0)), None);
+    }
+
+    #[test]
+    fn display_transform_window_larger_than_image() {
+        let transform = DisplayTransform::new((100, 50), (500, 500), false);
+        assert_eq!(transform.display_size, (100.0, 50.0));
+        assert_eq!(transform.offset, (200.0, 225.0));
+        assert_eq!(
+            transform.subpixel_position((200.0, 225.0)),
+            Some((0.0, 0.0))
+        );
+        assert_eq!(
+            transform.subpixel_position((300.0, 275.0)),
+            Some((100.0, 50.0))
+        );
+        assert_eq!(transform.subpixel_position((199.0, 250.0)), None);
+
+        let transform = DisplayTransform::new((100, 50), (500, 500), true);
+        assert_eq!(transform.display_size, (500.0, 250.0));
+        assert_eq!(
+            transform.subpixel_position((255.0, 250.0)),
+            Some((51.0, 25.0))
+        );
+    }
+
+    #[test]
+    fn display_transform_window_equal_to_image() {
+        let transform = DisplayTransform::new((500, 500), (500, 500), false);
+        assert_eq!(transform.display_size, (500.0, 500.0));
+        assert_eq!(transform.offset, (0.0, 0.0));
+        assert_eq!(
+            transform.subpixel_position((123.0, 456.0)),
+            Some((123.0, 456.0))
+        );
+    }
+
+    /// Half the width and height of the quad, in clip space.
+    fn quad_size(vertices: &[Vertex; 4]) -> (f32, f32) {
+        let [x, y] = *vertices[2].0;
+        (x, y)
+    }
+
+    #[test]
+    fn vertices_of_small_image() {
+        let vertices = calculate_vertices(10, 10, 500, 500, Crop::default(), false);
+        assert_eq!(quad_size(&vertices), (0.02, 0.02));
+        let vertices = calculate_vertices(10, 10, 500, 500, Crop::default(), true);
+        assert_eq!(quad_size(&vertices), (1.0, 1.0));
+        let [x, y] = *vertices[0].0;
+        assert_eq!((x, y), (-1.0, -1.0));
+    }
+
+    #[test]
+    fn vertices_of_cropped_image() {
+        let crop = Crop {
+            left: 2,
+            right: 0,
+            top: 0,
+            bottom: 5,
+        };
+        let vertices = calculate_vertices(10, 10, 500, 500, crop, true);
+        // an 8x5 region is limited by its width
+        assert_eq!(quad_size(&vertices), (1.0, 0.625));
+        let Vertex(_, left, right, top, bottom) = vertices[0];
+        assert_eq!((*left, *right, *top, *bottom), (0.2, 1.0, 0.0, 0.5));
+    }
+
+    #[test]
+    fn apply_gamma_corrects_colors_but_not_alpha() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([64, 128, 255, 64])));
+        let corrected = apply_gamma(image, 0.5);
+        assert_eq!(corrected.get_pixel(0, 0).0, [128, 181, 255, 64]);
+
+        let gray: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_pixel(1, 1, Luma([16384]));
+        let corrected = apply_gamma(DynamicImage::ImageLuma16(gray), 0.5);
+        assert_eq!(corrected.as_luma16().unwrap().get_pixel(0, 0).0, [32768]);
+    }
+
+    #[test]
+    fn apply_gamma_of_one_is_unchanged() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 16, |x, y| {
+            Rgb([x as u8 * 16, y as u8 * 16, 7])
+        }));
+        assert_eq!(apply_gamma(image.clone(), 1.0), image);
+    }
+
+    #[test]
+    fn suffixed_path_inserts_before_extension() {
+        assert_eq!(suffixed_path("photo.png", "crop"), "photo-crop.png");
+        assert_eq!(suffixed_path("dir/scan.tar.png", "2"), "dir/scan.tar-2.png");
+        assert_eq!(suffixed_path("noext", "1"), "noext-1");
+    }
+
+    #[test]
+    fn numbered_path_skips_existing_files() {
+        let existing = ["page-1.png", "page-2.png", "page-4.png"];
+        let exists = |path: &str| existing.contains(&path);
+        assert_eq!(
+            numbered_path("page.png", 1, exists),
+            (3, "page-3.png".to_string())
+        );
+        assert_eq!(
+            numbered_path("page.png", 4, exists),
+            (5, "page-5.png".to_string())
+        );
+        assert_eq!(
+            numbered_path("other.png", 1, exists),
+            (1, "other-1.png".to_string())
+        );
+    }
+
+    #[test]
+    fn size_report_after_crop() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(100, 80));
+        let original = image.dimensions();
+        let crop = Crop {
+            left: 10,
+            right: 5,
+            top: 0,
+            bottom: 30,
+        };
+        let cropped = crop_image(image, crop);
+        assert_eq!(
+            size_report(original, cropped.dimensions()),
+            "original size: 100x80\ncropped size: 85x50\n"
+        );
+    }
+
+    #[test]
+    fn bbox_to_crop_with_reversed_clicks() {
+        let forward = bbox_to_crop((100, 50), Crop::default(), (10, 5), (60, 45));
+        let reversed = bbox_to_crop((100, 50), Crop::default(), (60, 45), (10, 5));
+        let crossed = bbox_to_crop((100, 50), Crop::default(), (10, 45), (60, 5));
+        let expected = Crop {
+            left: 10,
+            right: 40,
+            top: 5,
+            bottom: 5,
+        };
+        assert_eq!(forward, expected);
+        assert_eq!(reversed, expected);
+        assert_eq!(crossed, expected);
+    }
+
+    #[test]
+    fn clicks_beside_the_image_are_clamped_to_its_edges() {
+        // a 100x50 image in the middle of a 400x400 framebuffer
+        let transform = DisplayTransform::new((100, 50), (400, 400), false);
+        assert_eq!(transform.clamped_position((0.0, 0.0)), (0.0, 0.0));
+        assert_eq!(transform.clamped_position((399.0, 399.0)), (100.0, 50.0));
+        assert_eq!(transform.clamped_position((200.0, 0.0)), (50.0, 0.0));
+
+        let p1 = transform.clamped_position((175.0, 0.0));
+        let p2 = transform.clamped_position((399.0, 399.0));
+        let round = |(x, y): (f64, f64)| (x.round() as u32, y.round() as u32);
+        let crop = bbox_to_crop((100, 50), Crop::default(), round(p1), round(p2));
+        assert_eq!(
+            crop,
+            Crop {
+                left: 25,
+                right: 0,
+                top: 0,
+                bottom: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn clamped_position_of_empty_image() {
+        let transform = DisplayTransform::new((0, 0), (400, 400), true);
+        assert_eq!(transform.clamped_position((200.0, 200.0)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn grayscale_output_is_single_channel() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(40, 30, Rgba([255, 0, 0, 255])));
+        let gray = transform_output(image, &args(&["--grayscale"])).unwrap();
+        assert_eq!(gray.color(), ColorType::L8);
+        assert_eq!(gray.dimensions(), (40, 30));
+    }
+
+    #[test]
+    fn crop_spec_round_trip() {
+        let crop = Crop {
+            left: 10,
+            right: 5,
+            top: 0,
+            bottom: 7,
+        };
+        assert_eq!(crop.to_string(), "l:10,r:5,t:0,b:7");
+        assert_eq!("l:10,r:5,t:0,b:7".parse(), Ok(crop));
+        assert_eq!(crop.to_string().parse(), Ok(crop));
+        // sides may be reordered or left out
+        assert_eq!("b:7, l:10,r:5".parse::<Crop>(), Ok(crop),);
+    }
+
+    #[test]
+    fn crop_spec_errors() {
+        assert!("".parse::<Crop>().is_err());
+        assert!("l:10,x:5".parse::<Crop>().is_err());
+        assert!("l10".parse::<Crop>().is_err());
+        assert!("l:-1".parse::<Crop>().is_err());
+    }
+
+    #[test]
+    fn compose_adds_sides() {
+        let a = Crop {
+            left: 1,
+            right: 2,
+            top: 3,
+            bottom: 4,
+        };
+        let b = Crop {
+            left: 10,
+            right: 20,
+            top: 30,
+            bottom: 40,
+        };
+        let expected = Crop {
+            left: 11,
+            right: 22,
+            top: 33,
+            bottom: 44,
+        };
+        assert_eq!(a.compose(b), expected);
+        assert_eq!(a.compose(Crop::default()), a);
+
+        // cropping the composed crop is the same as cropping twice
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(100, 100, |x, y| {
+            Rgba([x as u8, y as u8, 0, 255])
+        }));
+        assert_eq!(
+            crop_image(image.clone(), a.compose(b)),
+            crop_image(crop_image(image, a), b)
+        );
+    }
+
+    #[test]
+    fn compose_saturates() {
+        let a = Crop {
+            left: u32::MAX,
+            ..Crop::default()
+        };
+        let b = Crop {
+            left: 1,
+            ..Crop::default()
+        };
+        assert_eq!(a.compose(b).left, u32::MAX);
+        assert!(check_crop(a.compose(b), (100, 100)).is_err());
+    }
+
+    #[test]
+    fn dry_run_reports_size_without_writing() {
+        let path = temp_path("dry-run.png");
+        let image = crop_image(
+            DynamicImage::ImageRgba8(RgbaImage::new(100, 80)),
+            Crop {
+                left: 10,
+                right: 10,
+                top: 0,
+                bottom: 40,
+            },
+        );
+        let args = args(&["-o", &path, "--rotate", "90", "--scale", "0.5"]);
+        assert_eq!(
+            dry_run_report(&image, &args),
+            Ok(format!("size: 20x40\noutput: {}\n", path))
+        );
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn output_size_matches_transform_output() {
+        let args = args(&["--rotate", "270", "--scale", "3", "--scale-x", "2"]);
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(7, 5));
+        let size = output_size(image.dimensions(), &args).unwrap();
+        assert_eq!(size, (30, 21));
+        assert_eq!(transform_output(image, &args).unwrap().dimensions(), size);
+    }
+
+    #[test]
+    fn scale_x_and_y_independently() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(100, 100));
+        let args = args(&["--scale-x", "2", "--scale-y", "1"]);
+        assert_eq!(output_size(image.dimensions(), &args), Ok((200, 100)));
+        let scaled = transform_output(image, &args).unwrap();
+        assert_eq!(scaled.dimensions(), (200, 100));
+    }
+
+    #[test]
+    fn empty_image() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(0, 0));
+        assert_eq!(
+            crop_image(image.clone(), Crop::default()).dimensions(),
+            (0, 0)
+        );
+        assert_eq!(
+            transform_output(image, &args(&[])).err(),
+            Some("Cannot save an empty image".to_string())
+        );
+
+        for vertices in [
+            calculate_vertices(0, 0, 500, 500, Crop::default(), true),
+            calculate_vertices(10, 10, 0, 0, Crop::default(), true),
+        ] {
+            for Vertex(position, left, right, top, bottom) in vertices {
+                let [x, y] = *position;
+                assert!([x, y, *left, *right, *top, *bottom]
+                    .iter()
+                    .all(|v| v.is_finite()));
+            }
+        }
+    }
+
+    #[test]
+    fn grayscale_png_round_trip_keeps_16_bits() {
+        let input = temp_path("gray16-in.png");
+        let output = temp_path("gray16-out.png");
+        let gray: ImageBuffer<Luma<u16>, Vec<u16>> =
+            ImageBuffer::from_fn(4, 2, |x, y| Luma([(x * 1000 + y * 7 + 3) as u16]));
+        DynamicImage::ImageLuma16(gray.clone())
+            .save(&input)
+            .unwrap();
+        let image = image::open(&input).unwrap();
+        let image = transform_output(image, &args(&["--grayscale"])).unwrap();
+        save_image(&image, &output, 75).unwrap();
+        let saved = image::open(&output).unwrap();
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&output).unwrap();
+        assert_eq!(saved.color(), ColorType::L16);
+        assert_eq!(saved.to_luma16(), gray);
+    }
+
+    #[test]
+    fn background_of_16_bit_image_keeps_16_bits() {
+        let image: ImageBuffer<Rgba<u16>, Vec<u16>> =
+            ImageBuffer::from_pixel(2, 1, Rgba([1001, 2002, 3003, 65535]));
+        let image = DynamicImage::ImageRgba16(image);
+        let flat = transform_output(image, &args(&["--background", "ffffff"])).unwrap();
+        assert_eq!(flat.color(), ColorType::Rgb16);
+        assert_eq!(flat.to_rgb16().get_pixel(0, 0).0, [1001, 2002, 3003]);
+    }
+
+    #[test]
+    fn convert_bit_depth_scales_16_to_8_bits() {
+        let samples = [0, 128, 257, 32896, 65535];
+        let image: ImageBuffer<Luma<u16>, Vec<u16>> =
+            ImageBuffer::from_fn(5, 1, |x, _| Luma([samples[x as usize]]));
+        let image = convert_bit_depth(DynamicImage::ImageLuma16(image), 8);
+        assert_eq!(image.color(), ColorType::L8);
+        assert_eq!(image.as_bytes(), &[0, 0, 1, 128, 255]);
+
+        let image = convert_bit_depth(image, 16);
+        assert_eq!(image.color(), ColorType::L16);
+        assert_eq!(image.to_luma16().into_raw(), vec![0, 0, 257, 32896, 65535]);
+    }
+
+    /// A 6x5 black image with a white 2x3 rectangle at (1, 1), next to a light gray
+    /// column at x = 3.
+    fn wand_image() -> RgbaImage {
+        RgbaImage::from_fn(6, 5, |x, y| match (x, y) {
+            (1..=2, 1..=3) => Rgba([255, 255, 255, 255]),
+            (3, 1..=3) => Rgba([250, 250, 250, 255]),
+            _ => Rgba([0, 0, 0, 255]),
+        })
+    }
+
+    #[test]
+    fn region_bbox_stops_at_different_pixels() {
+        let image = wand_image();
+        assert_eq!(
+            region_bbox(&image, Crop::default(), (2, 2), 0),
+            ((1, 1), (3, 4))
+        );
+        // the black border surrounds the rectangle, so it spans the whole image
+        assert_eq!(
+            region_bbox(&image, Crop::default(), (0, 0), 0),
+            ((0, 0), (6, 5))
+        );
+    }
+
+    #[test]
+    fn region_bbox_with_tolerance() {
+        let image = wand_image();
+        assert_eq!(
+            region_bbox(&image, Crop::default(), (1, 1), 4),
+            ((1, 1), (3, 4))
+        );
+        assert_eq!(
+            region_bbox(&image, Crop::default(), (1, 1), 5),
+            ((1, 1), (4, 4))
+        );
+    }
+
+    #[test]
+    fn region_bbox_is_relative_to_the_crop() {
+        let image = wand_image();
+        let crop = Crop {
+            left: 2,
+            right: 0,
+            top: 1,
+            bottom: 0,
+        };
+        // (0, 0) of the cropped image is the white pixel at (2, 1)
+        let (p1, p2) = region_bbox(&image, crop, (0, 0), 5);
+        assert_eq!((p1, p2), ((0, 0), (2, 3)));
+        assert_eq!(
+            bbox_to_crop(image.dimensions(), crop, p1, p2),
+            Crop {
+                left: 2,
+                right: 2,
+                top: 1,
+                bottom: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn subpixel_crop_on_whole_pixels_matches_integer_crop() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 6, |x, y| {
+            Rgba([(x * 30) as u8, (y * 40) as u8, (x * y) as u8, 255])
+        }));
+        let crop = Crop {
+            left: 1,
+            right: 2,
+            top: 3,
+            bottom: 1,
+        };
+        let exact = SubpixelCrop {
+            left: 1.0,
+            right: 2.0,
+            top: 3.0,
+            bottom: 1.0,
+        };
+        let resampled = crop_subpixel(&image, exact, crop);
+        assert_eq!(resampled.color(), ColorType::Rgba8);
+        assert_eq!(resampled.to_rgba8(), crop_image(image, crop).to_rgba8());
+    }
+
+    #[test]
+    fn subpixel_crop_blends_and_has_the_rounded_size() {
+        let image = DynamicImage::ImageLuma8(ImageBuffer::from_fn(10, 4, |x, _| {
+            Luma([if x < 5 { 0 } else { 200 }])
+        }));
+        // a 9.2 pixel wide region rounds to a crop that keeps all 10 columns
+        let exact = SubpixelCrop {
+            left: 0.4,
+            right: 0.4,
+            top: 0.0,
+            bottom: 0.0,
+        };
+        let resampled = crop_subpixel(&image, exact, Crop::default());
+        assert_eq!(resampled.dimensions(), (10, 4));
+
+        // shifting by half a pixel blends the columns on either side of the edge
+        let exact = SubpixelCrop {
+            left: 4.5,
+            right: 4.5,
+            top: 1.0,
+            bottom: 2.0,
+        };
+        let crop = Crop {
+            left: 5,
+            right: 4,
+            top: 1,
+            bottom: 2,
+        };
+        let resampled = crop_subpixel(&image, exact, crop);
+        assert_eq!(resampled.dimensions(), (1, 1));
+        assert_eq!(resampled.as_bytes(), &[100]);
+    }
+
+    #[test]
+    fn pan_crop_stops_at_the_edges() {
+        let crop = Crop {
+            left: 3,
+            right: 5,
+            top: 0,
+            bottom: 2,
+        };
+        assert_eq!(
+            pan_crop(crop, (2, 1)),
+            Crop {
+                left: 5,
+                right: 3,
+                top: 1,
+                bottom: 1,
+            }
+        );
+        // panning past an edge moves the region right up to it
+        assert_eq!(
+            pan_crop(crop, (-10, -1)),
+            Crop {
+                left: 0,
+                right: 8,
+                top: 0,
+                bottom: 2,
+            }
+        );
+        assert_eq!(
+            pan_crop(crop, (10, 10)),
+            Crop {
+                left: 8,
+                right: 0,
+                top: 2,
+                bottom: 0,
+            }
+        );
+        // an uncropped image has nowhere to pan to
+        assert_eq!(pan_crop(Crop::default(), (1, -1)), Crop::default());
+    }
+
+    #[test]
+    fn recenter_crop_balances_the_edges() {
+        let crop = Crop {
+            left: 6,
+            right: 0,
+            top: 1,
+            bottom: 4,
+        };
+        let centered = recenter_crop(crop, (20, 10));
+        assert_eq!(
+            centered,
+            Crop {
+                left: 3,
+                right: 3,
+                top: 2,
+                bottom: 3,
+            }
+        );
+        // the size is kept, and opposite edges differ by at most one pixel
+        assert_eq!(centered.left + centered.right, crop.left + crop.right);
+        assert_eq!(centered.top + centered.bottom, crop.top + crop.bottom);
+        assert_eq!(recenter_crop(centered, (20, 10)), centered);
+        assert_eq!(recenter_crop(Crop::default(), (20, 10)), Crop::default());
+    }
+
+    #[test]
+    fn watched_file_reloads_once_a_new_time_is_seen_twice() {
+        let start = SystemTime::UNIX_EPOCH;
+        let later = start + Duration::from_secs(1);
+        let mut watched = WatchedFile::new(Some(start));
+        assert!(!watched.should_reload(Some(start)));
+        assert!(!watched.should_reload(Some(later)));
+        assert!(watched.should_reload(Some(later)));
+        assert_eq!(watched, WatchedFile::new(Some(later)));
+        assert!(!watched.should_reload(Some(later)));
+    }
+
+    #[test]
+    fn watched_file_ignores_times_seen_once() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut watched = WatchedFile::new(Some(start));
+        // the file is still being written, so each check sees a newer time
+        for seconds in 1..4 {
+            assert!(!watched.should_reload(Some(start + Duration::from_secs(seconds))));
+        }
+        assert!(!watched.should_reload(Some(start)));
+    }
+
+    #[test]
+    fn watched_file_waits_while_the_file_is_missing() {
+        let start = SystemTime::UNIX_EPOCH;
+        let later = start + Duration::from_secs(1);
+        let mut watched = WatchedFile::new(Some(start));
+        assert!(!watched.should_reload(Some(later)));
+        assert!(!watched.should_reload(None));
+        assert_eq!(watched, WatchedFile::new(Some(start)));
+        // the new time has to be seen twice again after the file reappears
+        assert!(!watched.should_reload(Some(later)));
+        assert!(watched.should_reload(Some(later)));
+    }
+
+    #[test]
+    fn apply_gamma_must_be_positive() {
+        assert_eq!(parse_gamma("2.2"), Ok(2.2));
+        for value in ["0", "-2.2", "NaN", "inf", "dark"] {
+            assert!(parse_gamma(value).is_err(), "{}", value);
+        }
+        assert!(PNGArgs::from_args(&["motsu"], &["--apply-gamma", "0", "input.png"]).is_err());
+    }
+
+    #[test]
+    fn nearest_filter_keeps_hard_edges() {
+        let checkerboard = ImageBuffer::from_fn(2, 2, |x, y| Luma([if x == y { 0 } else { 255 }]));
+        let image = DynamicImage::ImageLuma8(checkerboard);
+        let scaled = transform_output(
+            image.clone(),
+            &args(&["--scale", "4", "--filter", "nearest"]),
+        )
+        .unwrap();
+        assert_eq!(scaled.dimensions(), (8, 8));
+        assert!(scaled
+            .as_bytes()
+            .iter()
+            .all(|&sample| sample == 0 || sample == 255));
+
+        // the default filter blends across the edges
+        let scaled = transform_output(image, &args(&["--scale", "4"])).unwrap();
+        assert!(scaled
+            .as_bytes()
+            .iter()
+            .any(|&sample| sample != 0 && sample != 255));
+
+        assert!(PNGArgs::from_args(&["motsu"], &["--filter", "cubic", "input.png"]).is_err());
+    }
+
+    #[test]
+    fn grayscale_png_stays_grayscale_without_options() {
+        let gray = ImageBuffer::from_fn(3, 2, |x, y| Luma([(x * 80 + y) as u8]));
+        let gray_alpha =
+            ImageBuffer::from_fn(3, 2, |x, y| LumaA([(x * 80) as u8, (y * 100) as u8]));
+        for (name, image, color) in [
+            ("gray8.png", DynamicImage::ImageLuma8(gray), ColorType::L8),
+            (
+                "gray-alpha8.png",
+                DynamicImage::ImageLumaA8(gray_alpha),
+                ColorType::La8,
+            ),
+        ] {
+            let input = temp_path(&format!("in-{}", name));
+            let output = temp_path(&format!("out-{}", name));
+            image.save(&input).unwrap();
+            let loaded = image::open(&input).unwrap();
+            let transformed = transform_output(loaded, &args(&[])).unwrap();
+            save_image(&transformed, &output, 75).unwrap();
+            let saved = image::open(&output).unwrap();
+            fs::remove_file(&input).unwrap();
+            fs::remove_file(&output).unwrap();
+            assert_eq!(saved.color(), color, "{}", name);
+            assert_eq!(saved, image, "{}", name);
+        }
+    }
+
+    #[test]
+    fn pixel_position_belongs_to_the_pixel_under_it() {
+        // a 100x50 image drawn at 5x scale, offset by (0, 125)
+        let transform = DisplayTransform::new((100, 50), (500, 500), true);
+        assert_eq!(transform.pixel_position((0.0, 125.0)), Some((0, 0)));
+        // just short of the next pixel is still the same pixel, rather than rounding up
+        assert_eq!(transform.pixel_position((9.9, 134.9)), Some((1, 1)));
+        // the right and bottom edges belong to the last pixel
+        assert_eq!(transform.pixel_position((500.0, 375.0)), Some((99, 49)));
+        assert_eq!(transform.pixel_position((250.0, 124.0)), None);
+    }
 }